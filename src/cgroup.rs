@@ -0,0 +1,144 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+/// cgroup v2のマウント先
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// `Placement::Child`でデバック対象のcgroupの下に作成するcgroupの名前
+const CHILD_NAME: &str = "injesh";
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownPlacement(String),
+    NotCgroupV2,
+    CgroupNotFound(u32),
+    CreateFailed(PathBuf, std::io::Error),
+    MoveFailed(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownPlacement(placement) => {
+                write!(f, "cgroup: unknown placement {}", placement)
+            }
+            Error::NotCgroupV2 => write!(f, "cgroup: {} is not cgroup v2", CGROUP_ROOT),
+            Error::CgroupNotFound(pid) => {
+                write!(f, "cgroup: cgroup v2 of process {} not found", pid)
+            }
+            Error::CreateFailed(path, why) => {
+                write!(f, "cgroup: failed to create {}: {}", path.display(), why)
+            }
+            Error::MoveFailed(path, why) => {
+                write!(f, "cgroup: failed to move into {}: {}", path.display(), why)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// デバックセッションを置くcgroup
+///
+/// デバック中のメモリ、CPUの使用量がデバック対象コンテナのものとして計上され、制限される
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    /// デバック対象コンテナのcgroup
+    Target,
+    /// デバック対象コンテナのcgroupの下の`injesh`cgroup
+    Child,
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Placement::Target => write!(f, "target"),
+            Placement::Child => write!(f, "child"),
+        }
+    }
+}
+
+impl std::str::FromStr for Placement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "target" => Ok(Placement::Target),
+            "child" => Ok(Placement::Child),
+            _ => Err(Error::UnknownPlacement(s.to_string())),
+        }
+    }
+}
+
+impl Placement {
+    /// デバック対象のプロセス`target_pid`に対して、セッションを置くcgroupの`cgroup.procs`を開く
+    ///
+    /// `Child`の場合は`injesh`cgroupが無ければ作成する。
+    /// setnsの後はホストのcgroupを参照できないため、事前に開いておく
+    pub fn open(&self, target_pid: u32) -> Result<Procs, Box<dyn std::error::Error>> {
+        if !Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+            Err(Error::NotCgroupV2)?
+        }
+        let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", target_pid))?;
+        let cgroup = parse_cgroup(&cgroup).ok_or(Error::CgroupNotFound(target_pid))?;
+
+        let mut path = Path::new(CGROUP_ROOT).join(cgroup.trim_start_matches('/'));
+        if *self == Placement::Child {
+            path.push(CHILD_NAME);
+            if let Err(why) = fs::create_dir(&path) {
+                if why.kind() != std::io::ErrorKind::AlreadyExists {
+                    Err(Error::CreateFailed(path.clone(), why))?
+                }
+            }
+        }
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))
+            .map_err(|why| Error::MoveFailed(path.clone(), why))?;
+
+        Ok(Procs { path, procs })
+    }
+}
+
+/// セッションを置くcgroupの`cgroup.procs`
+pub struct Procs {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Procs {
+    /// 呼び出したプロセスをこのcgroupに移動する
+    ///
+    /// 移動後にforkした子プロセスも、このcgroupに属する。cgroup namespaceに入る前に呼ぶ
+    pub fn enter(&self) -> Result<(), Error> {
+        // 0は書き込んだプロセス自身を表す
+        (&self.procs)
+            .write_all(b"0")
+            .map_err(|why| Error::MoveFailed(self.path.clone(), why))
+    }
+}
+
+/// `/proc/<PID>/cgroup`からcgroup v2のパスを取り出す
+fn parse_cgroup(cgroup: &str) -> Option<&str> {
+    cgroup.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup() {
+        assert_eq!(
+            parse_cgroup("0::/system.slice/docker-abcd.scope\n"),
+            Some("/system.slice/docker-abcd.scope")
+        );
+        assert_eq!(
+            parse_cgroup("12:pids:/docker/abcd\n1:name=systemd:/docker/abcd\n0::/docker/abcd\n"),
+            Some("/docker/abcd")
+        );
+        assert_eq!(parse_cgroup("12:pids:/docker/abcd\n"), None);
+    }
+}
@@ -4,3 +4,8 @@ pub mod exec;
 pub mod init;
 pub mod launch;
 pub mod list;
+pub mod ns;
+pub mod session;
+mod sideload;
+mod terminal;
+pub mod watch;
@@ -1,20 +1,28 @@
 use crate::container;
 
 use nix::{
-    mount::{mount, MsFlags},
-    unistd::{Gid, Uid},
+    errno::Errno,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    unistd::{chdir, chroot, fchdir, Gid, Uid},
 };
 use std::{
     error, fmt,
-    fs::{copy, create_dir, read_dir, OpenOptions},
+    fs::{copy, create_dir, metadata, read_dir, File, OpenOptions},
+    os::unix::{fs::MetadataExt, io::AsRawFd},
     path::{Path, PathBuf},
 };
 
+/// デバックコンテナ内で実行したプロセスに設定する環境変数。値はデバックコンテナの名前
+///
+/// 実行中のセッションを`/proc/<PID>/environ`から探すために用いる
+pub const SESSION_ENV: &str = "INJESH_CONTAINER";
+
 #[derive(Debug)]
 pub enum Error {
     InvalidPath(PathBuf),
     OvarlayfsDirInvalid,
     MountFailed(nix::errno::Errno),
+    UnmountFailed(nix::errno::Errno),
 }
 
 impl fmt::Display for Error {
@@ -23,6 +31,9 @@ impl fmt::Display for Error {
             Error::InvalidPath(path) => write!(f, "cmd::common::InvalidPath: {:?}", path),
             Error::OvarlayfsDirInvalid => write!(f, "cmd::common::OvarlayfsDirInvalid"),
             Error::MountFailed(why) => write!(f, "cmd::command::MountFailed: because of {}", why),
+            Error::UnmountFailed(why) => {
+                write!(f, "cmd::command::UnmountFailed: because of {}", why)
+            }
         }
     }
 }
@@ -60,6 +71,79 @@ pub fn new_gidmap(gid: &Gid) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// rootfsを挿入したoverlayfsをデバックコンテナの`merged`にマウントする
+///
+/// lowerdirはrootfs、デバック対象コンテナのルートファイルシステムの順に重ねる。
+/// `target_root`が`None`の場合はrootfsのみを使う。
+/// upperdirはデバックコンテナの`upper`のため、再マウントしても変更は失われない
+pub fn mount_debug_overlay(
+    dcontainer_base: &Path,
+    rootfs: &Path,
+    target_root: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path_str = |path: &Path| -> Result<String, Error> {
+        path.to_str()
+            .map(|path| path.to_string())
+            .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))
+    };
+
+    let lowerdir = match target_root {
+        Some(target_root) => format!("{}:{}", path_str(rootfs)?, path_str(target_root)?),
+        None => path_str(rootfs)?,
+    };
+    let mount_data = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lowerdir,
+        path_str(&dcontainer_base.join("upper"))?,
+        path_str(&dcontainer_base.join("worker"))?
+    );
+    mount(
+        Some("overlay"),
+        &dcontainer_base.join("merged"),
+        Some("overlay"),
+        MsFlags::empty(),
+        Some(mount_data.as_str()),
+    )
+    .map_err(Error::MountFailed)?;
+
+    Ok(())
+}
+
+/// デバックコンテナの`merged`をアンマウントする
+///
+/// デバック対象コンテナは終了しているため、使用中でも切り離す。マウントされていなければ何もしない
+pub fn unmount_debug_overlay(dcontainer_base: &Path) -> Result<(), Error> {
+    match umount2(&dcontainer_base.join("merged"), MntFlags::MNT_DETACH) {
+        Ok(_) | Err(Errno::EINVAL) => Ok(()),
+        Err(why) => Err(Error::UnmountFailed(why)),
+    }
+}
+
+/// `path`がマウントポイントかどうか
+///
+/// 親ディレクトリとデバイスが異なればマウントされている
+pub fn is_mounted(path: &Path) -> bool {
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return true,
+    };
+    match (metadata(path), metadata(parent)) {
+        (Ok(path), Ok(parent)) => path.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+/// 事前に開いたディレクトリ`dir`をルートディレクトリにする
+///
+/// デバック対象のuser namespaceに入った後は、ホストのホームディレクトリを辿れないことがあるため、パスを用いない
+pub fn chroot_dir(dir: &File) -> Result<(), Box<dyn std::error::Error>> {
+    fchdir(dir.as_raw_fd())?;
+    chroot(".")?;
+    chdir("/")?;
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 fn copy_dir_recursively(src: &PathBuf, dest: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     let src_pathbuf = src.clone();
@@ -98,7 +182,9 @@ fn overwrite_target_upperdir_by_own_upperdir(
     container_dir_path: &Path,
     overlayfs_dirs: &container::Container,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let target_upperdir = overlayfs_dirs.upperdir();
+    let target_upperdir = overlayfs_dirs
+        .upperdir()
+        .ok_or(Error::OvarlayfsDirInvalid)?;
     let own_upperdir = container_dir_path.join("upper");
 
     copy_dir_recursively(&own_upperdir, target_upperdir)?;
@@ -112,18 +198,21 @@ fn mount_merged_directory(
 ) -> Result<(), Box<dyn std::error::Error>> {
     let lowerdir_string = overlayfs_dirs
         .lowerdir()
+        .ok_or(Error::OvarlayfsDirInvalid)?
         .clone()
         .into_os_string()
         .into_string()
         .map_err(|_| Error::OvarlayfsDirInvalid)?;
     let upperdir_string = overlayfs_dirs
         .upperdir()
+        .ok_or(Error::OvarlayfsDirInvalid)?
         .clone()
         .into_os_string()
         .into_string()
         .map_err(|_| Error::OvarlayfsDirInvalid)?;
     let workdir_string = overlayfs_dirs
         .workdir()
+        .ok_or(Error::OvarlayfsDirInvalid)?
         .clone()
         .into_os_string()
         .into_string()
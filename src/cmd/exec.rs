@@ -1,15 +1,20 @@
-use crate::{cmd::common, command, container, image_downloader, namespace, setting, user, utils};
+use crate::docker_client::DockerClient;
+use crate::{
+    cmd::common, cmd::session, cmd::sideload, cmd::terminal, command, container, image_downloader,
+    namespace, security, setting, user, utils,
+};
 
 use nix::sched::{unshare, CloneFlags};
 use nix::sys::wait::waitpid;
-use nix::unistd::{chdir, chroot, fork, ForkResult, Gid, Uid};
-use std::path::PathBuf;
-use std::{error, fmt};
+use nix::unistd::{fork, ForkResult, Gid, Uid};
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs};
 
 #[derive(Debug)]
 pub enum Error {
     Waitpid,
     Fork,
+    RootfsUnknown(String),
 }
 
 impl fmt::Display for Error {
@@ -17,68 +22,297 @@ impl fmt::Display for Error {
         match self {
             Error::Waitpid => write!(f, "cmd::exec: failed waitpid"),
             Error::Fork => write!(f, "cmd::exec: failed fork"),
+            Error::RootfsUnknown(name) => write!(
+                f,
+                "cmd::exec: rootfs of {} is not recorded, so it cannot be remounted. delete and launch it again",
+                name
+            ),
         }
     }
 }
 
 impl error::Error for Error {}
 
+/// launch時から変化したデバック対象コンテナの状態
+#[derive(Debug, PartialEq)]
+enum TargetChange {
+    /// 同じ名前で別のコンテナとして作り直された
+    Recreated { old_id: String, new_id: String },
+    /// 同じコンテナのプロセスが起動し直した
+    Restarted { old_pid: u32, new_pid: u32 },
+    /// デバック対象は変わらないが、デバックコンテナがマウントされていない
+    Unmounted,
+}
+
+impl fmt::Display for TargetChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetChange::Recreated { old_id, new_id } => write!(
+                f,
+                "target container was recreated (ID {} -> {})",
+                container::short_id(old_id),
+                container::short_id(new_id)
+            ),
+            TargetChange::Restarted { old_pid, new_pid } => write!(
+                f,
+                "target container was restarted (PID {} -> {})",
+                old_pid, new_pid
+            ),
+            TargetChange::Unmounted => write!(f, "debug container was not mounted"),
+        }
+    }
+}
+
 pub struct ExecStruct;
 
 impl ExecStruct {
+    /// デバックコンテナ内でコマンドを実行し、その終了コードを返す
     pub fn exec<D: image_downloader::Downloader, RW: setting::Reader + setting::Writer>(
         &self,
         exec: &mut command::Exec<D, RW>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<i32, Box<dyn std::error::Error>> {
         utils::check_initialized()?;
 
+        let user = user::User::new()?;
+        let name = exec.name().to_string();
+        let dcontainer_base = PathBuf::from(format!("{}/{}", user.containers(), name));
+
         let dcontainer_setting = exec.setting_mut().read()?;
-        let container = container::Container::new(dcontainer_setting.docker_container_id())?;
+        let mut container = resolve_target(dcontainer_setting)?;
+        if dcontainer_setting.fs_view() != container::FsView::MergedDir {
+            container.use_proc_root();
+        }
+        // PIDで指定した場合、PIDが別のプロセスに再利用されていないか確認
+        if let (container::Runtime::Pid, Some(start_time)) =
+            (container.runtime(), dcontainer_setting.start_time())
+        {
+            container.check_start_time(start_time)?;
+        }
+
+        let change = detect_change(
+            dcontainer_setting,
+            &container,
+            common::is_mounted(&dcontainer_base.join("merged")),
+        )?;
+        let rootfs = dcontainer_setting.rootfs().map(Path::to_path_buf);
+        let launch_namespaces = dcontainer_setting.namespaces().to_vec();
+        let mount_namespace = dcontainer_setting.mount_namespace();
+        let launch_cgroup = dcontainer_setting.cgroup();
+        let launch_match_security = dcontainer_setting.match_security();
+        if let Some(change) = change {
+            let rootfs = rootfs.ok_or_else(|| Error::RootfsUnknown(name.clone()))?;
+            // 古いlowerdirを参照しているoverlayfsを、新しいデバック対象のルートファイルシステムで張り直す
+            common::unmount_debug_overlay(&dcontainer_base)?;
+            common::mount_debug_overlay(&dcontainer_base, &rootfs, container.target_root())?;
+
+            let start_time = container.start_time()?;
+            let setting = exec.setting_mut().read_mut()?;
+            setting.set_docker_container_id(container.container_id());
+            setting.set_process(container.pid(), start_time);
+            exec.setting().write()?;
+            println!(
+                "{}: remounted {} on the current target and joining its namespaces",
+                change, name
+            );
+        }
 
+        // launch時の選択に、execでの指定を適用する
+        let namespaces = exec.namespaces().apply(&launch_namespaces);
         let container_pid = container.pid();
-        let ns = namespace::Ns::new(container_pid)?;
+        // launch時、または張り直した時に記録したプロセスのnamespaceに入る
+        let start_time = match exec.setting_mut().read()?.start_time() {
+            Some(start_time) => start_time,
+            None => container.start_time()?,
+        };
+        let ns = namespace::Ns::new(container_pid, start_time)?;
+        let merged = fs::File::open(dcontainer_base.join("merged"))?;
+        let mut sideload = match mount_namespace {
+            true => Some(sideload::SideLoad::new(&dcontainer_base.join("merged"))?),
+            false => None,
+        };
+        let cgroup_procs = match exec.cgroup().or(launch_cgroup) {
+            Some(cgroup) => Some(cgroup.open(container_pid)?),
+            None => None,
+        };
+        let security = match exec.match_security() || launch_match_security {
+            true => Some(security::Context::read(container_pid)?),
+            false => None,
+        };
+        let mut terminal = terminal::Terminal::open(exec.tty(), exec.interactive())?;
+        if sideload.is_none() {
+            terminal.prepare_devpts(&dcontainer_base.join("merged"))?;
+        }
 
         unsafe {
             match fork() {
-                Ok(ForkResult::Parent { child, .. }) => match waitpid(child, None) {
-                    Ok(status) => println!("Child {:?}", status),
-                    Err(_) => Err(Error::Waitpid)?,
-                },
+                Ok(ForkResult::Parent { child, .. }) => {
+                    session::forward_signals(child, terminal.is_pty())?;
+                    terminal.proxy()?;
+                    match waitpid(child, None) {
+                        Ok(status) => Ok(session::exit_code(status)),
+                        Err(_) => Err(Error::Waitpid)?,
+                    }
+                }
                 Ok(ForkResult::Child) => {
-                    let gid = Gid::current();
-                    let uid = Uid::current();
-
-                    // setnsで名前空間を変更
-                    ns.setns_net()?;
-                    ns.setns_cgroup()?;
-                    ns.setns_ipc()?;
-                    ns.setns_pid()?;
-                    ns.setns_uts()?;
-                    unshare(CloneFlags::CLONE_NEWUSER)?;
-
-                    common::new_uidmap(&uid)?;
-                    common::new_gidmap(&gid)?;
-
-                    let user = user::User::new()?;
-                    let dcontainer_base = format!("{}/{}", user.containers(), exec.name());
-                    let dcontainer_base_merged = format!("{}/merged", &dcontainer_base);
-                    chroot(&PathBuf::from(&dcontainer_base_merged))?;
-                    chdir("/")?;
-
-                    // execでプログラムを実行
-                    use std::os::unix::process::CommandExt;
-                    std::process::Command::new(exec.cmd().main())
-                        .args(exec.cmd().detail())
-                        .exec();
+                    // execに失敗しても、injeshの処理には戻らずに終了する
+                    let mut run = || -> Result<(), Box<dyn std::error::Error>> {
+                        terminal.attach()?;
+                        let gid = Gid::current();
+                        let uid = Uid::current();
+
+                        // cgroup namespaceに入る前に、デバック対象のcgroupへ移動する
+                        if let Some(ref cgroup_procs) = cgroup_procs {
+                            cgroup_procs.enter()?;
+                        }
+
+                        // setnsで名前空間を変更
+                        ns.setns(&namespaces)?;
+                        // mount namespaceはホストの権限で入り、デバックコンテナを持ち込む
+                        if let Some(ref mut sideload) = sideload {
+                            ns.setns_mnt()?;
+                            sideload.attach()?;
+                        }
+                        // デバック対象のuser namespaceに入った場合は、そのマッピングをそのまま使う
+                        if !ns.joins_user() {
+                            unshare(CloneFlags::CLONE_NEWUSER)?;
+
+                            common::new_uidmap(&uid)?;
+                            common::new_gidmap(&gid)?;
+                        }
+
+                        let mut command = match sideload {
+                            Some(ref sideload) => sideload.command(exec.cmd())?,
+                            None => {
+                                terminal.bind_devpts()?;
+                                common::chroot_dir(&merged)?;
+
+                                let mut command = std::process::Command::new(exec.cmd().main());
+                                command.args(exec.cmd().detail());
+                                command
+                            }
+                        };
+                        command.env(common::SESSION_ENV, &name);
+                        // 最後にデバック対象と同じ権限に落とす
+                        if let Some(ref security) = security {
+                            security.apply()?;
+                        }
+
+                        // execでプログラムを実行
+                        Err(session::exec(&mut command, exec.cmd().main()))?
+                    };
+                    session::exit_child(run())
                 }
-                Err(_) => return Err(Error::Fork)?,
+                Err(_) => Err(Error::Fork)?,
             }
-        };
-
-        Ok(())
+        }
     }
 
     pub fn new() -> ExecStruct {
         ExecStruct
     }
 }
+
+/// 設定ファイルからデバック対象コンテナを選択し直す
+///
+/// ラベルで指定した場合、コンテナが再作成されていても再度選択する。
+/// IDのコンテナが存在しない場合は、launch時の名前で選択する
+pub(crate) fn resolve_target(
+    setting: &setting::Setting,
+) -> Result<container::Container, Box<dyn std::error::Error>> {
+    if let Some(selector) = setting.selector() {
+        return container::Container::from_selector(&mut DockerClient::from_env()?, selector);
+    }
+    if *setting.runtime() != container::Runtime::Docker {
+        return container::Container::with_runtime(
+            setting.runtime(),
+            setting.docker_container_id(),
+        );
+    }
+
+    // 名前で選択し直す場合も同じクライアントを使う
+    let mut client = DockerClient::from_env()?;
+    match container::Container::new(&mut client, setting.docker_container_id()) {
+        Err(e) if is_container_not_found(e.as_ref()) => match setting.target_name() {
+            Some(name) => container::Container::new(&mut client, name),
+            None => Err(e),
+        },
+        result => result,
+    }
+}
+
+fn is_container_not_found(e: &(dyn error::Error + 'static)) -> bool {
+    matches!(
+        e.downcast_ref::<container::Error>(),
+        Some(container::Error::ContainerNotFound)
+    )
+}
+
+/// launch時に記録したデバック対象と、現在のデバック対象を比べる
+///
+/// デバックコンテナの再マウントが必要な場合に、その理由を返す
+fn detect_change(
+    setting: &setting::Setting,
+    container: &container::Container,
+    mounted: bool,
+) -> Result<Option<TargetChange>, Box<dyn std::error::Error>> {
+    if container.container_id() != setting.docker_container_id() {
+        return Ok(Some(TargetChange::Recreated {
+            old_id: setting.docker_container_id().to_string(),
+            new_id: container.container_id().to_string(),
+        }));
+    }
+    if let (Some(pid), Some(start_time)) = (setting.pid(), setting.start_time()) {
+        if container.pid() != pid || container.start_time()? != start_time {
+            return Ok(Some(TargetChange::Restarted {
+                old_pid: pid,
+                new_pid: container.pid(),
+            }));
+        }
+    }
+    if !mounted {
+        return Ok(Some(TargetChange::Unmounted));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_change() {
+        let pid = std::process::id();
+        let start_time = container::start_time(pid).unwrap();
+        let target = |id: &str| {
+            container::Container::from_root(container::Runtime::Docker, id, pid, PathBuf::from("/"))
+        };
+        let mut setting = setting::Setting::new("abcd", setting::Shell::Bash, &[]);
+        setting.set_process(pid, start_time);
+
+        assert_eq!(
+            detect_change(&setting, &target("abcd"), true).unwrap(),
+            None
+        );
+        assert_eq!(
+            detect_change(&setting, &target("abcd"), false).unwrap(),
+            Some(TargetChange::Unmounted)
+        );
+        assert_eq!(
+            detect_change(&setting, &target("efgh"), true).unwrap(),
+            Some(TargetChange::Recreated {
+                old_id: "abcd".to_string(),
+                new_id: "efgh".to_string()
+            })
+        );
+
+        setting.set_process(1, 0);
+        assert_eq!(
+            detect_change(&setting, &target("abcd"), true).unwrap(),
+            Some(TargetChange::Restarted {
+                old_pid: 1,
+                new_pid: pid
+            })
+        );
+    }
+}
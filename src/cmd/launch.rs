@@ -1,13 +1,14 @@
+use crate::cmd::{common, session, sideload, terminal};
 use crate::command::{self, RootFSOption};
 use crate::image_downloader::Downloader;
-use crate::{namespace, setting, user, utils};
+use crate::{namespace, security, setting, user, utils};
 use std::path::{Path, PathBuf};
-use std::{error, fmt, fs::create_dir_all};
+use std::{error, fmt, fs, fs::create_dir_all};
 
 use nix::mount::{mount, MsFlags};
 use nix::sched::{unshare, CloneFlags};
 use nix::sys::wait::waitpid;
-use nix::unistd::{chdir, chroot, fork, ForkResult, Gid, Uid};
+use nix::unistd::{fork, ForkResult, Gid, Uid};
 
 #[derive(Debug)]
 pub enum Error {
@@ -52,31 +53,54 @@ impl LaunchStruct {
     /// forkする
     /// 取得したデバック対象コンテナプロセスIDをもとにsetnsをし、名前空間を同一にする
     /// 与えられた初期実行ファイルをexecする
+    /// 子プロセスの終了コードを返す
     pub fn launch<DO: Downloader, RW: setting::Reader + setting::Writer>(
         &self,
         launch: &mut command::Launch<DO, RW>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<i32, Box<dyn std::error::Error>> {
         // injeshコマンドが初期化されてるかどうかチェック
         utils::check_initialized()?;
 
-        // デバックコンテナの設定ファイル、ディレクトリ周りの初期化
-        initialize_setting(launch)?;
-
-        rootfs_injected_overlayfs_mount(launch)?;
+        self.prepare(launch)?;
 
         // デバック対象コンテナのプロセスIDとネームスペースのファイルディスクリプタを取得
         let container_pid = launch.target_container().pid();
-        let ns = namespace::Ns::new(container_pid)?;
+        let ns = namespace::Ns::new(container_pid, launch.target_container().start_time()?)?;
+        let user = user::User::new()?;
+        let merged_path = PathBuf::from(format!("{}/{}/merged", user.containers(), launch.name()));
+        let merged = fs::File::open(&merged_path)?;
+        // mount namespaceに入る場合は、ホストのパスを参照できるうちにデバックコンテナを複製しておく
+        let mut sideload = match launch.mount_namespace() {
+            true => Some(sideload::SideLoad::new(&merged_path)?),
+            false => None,
+        };
+        // デバック対象のnamespaceに入る前に、ホストから権限を読み取っておく
+        let security = match launch.match_security() {
+            true => Some(security::Context::read(container_pid)?),
+            false => None,
+        };
+        // 疑似端末はホストの/dev/ptmxから開く
+        let mut terminal = terminal::Terminal::open(launch.tty(), launch.interactive())?;
+        if sideload.is_none() {
+            terminal.prepare_devpts(&merged_path)?;
+        }
         // let gid = Gid::current();
         // let uid = Uid::current();
 
-        // setnsで名前空間を変更
-        ns.setns_net()?;
-        ns.setns_cgroup()?;
-        ns.setns_ipc()?;
-        ns.setns_pid()?;
-        ns.setns_uts()?;
-        unshare(CloneFlags::CLONE_NEWNS)?;
+        // cgroup namespaceに入る前に、デバック対象のcgroupへ移動する。forkした子プロセスも同じcgroupに属する
+        if let Some(cgroup) = launch.cgroup() {
+            cgroup.open(container_pid)?.enter()?;
+        }
+
+        // setnsで指定された名前空間を変更
+        ns.setns(launch.namespaces())?;
+        match sideload {
+            Some(ref mut sideload) => {
+                ns.setns_mnt()?;
+                sideload.attach()?;
+            }
+            None => unshare(CloneFlags::CLONE_NEWNS)?,
+        }
         // unshare(CloneFlags::CLONE_NEWUSER)?;
         // common::new_uidmap(&uid)?;
         // common::new_gidmap(&gid)?;
@@ -84,36 +108,67 @@ impl LaunchStruct {
         unsafe {
             match fork() {
                 // 親プロセスの場合
-                Ok(ForkResult::Parent { child, .. }) => match waitpid(child, None) {
-                    Ok(status) => println!("Child {:?}", status),
-                    Err(_) => Err(Error::Waitpid)?,
-                },
+                Ok(ForkResult::Parent { child, .. }) => {
+                    session::forward_signals(child, terminal.is_pty())?;
+                    terminal.proxy()?;
+                    match waitpid(child, None) {
+                        Ok(status) => Ok(session::exit_code(status)),
+                        Err(_) => Err(Error::Waitpid)?,
+                    }
+                }
                 // 子プロセス
                 Ok(ForkResult::Child) => {
-                    let user = user::User::new()?;
-                    let dcontainer_base = format!("{}/{}", user.containers(), launch.name());
-                    let dcontainer_base_merged = format!("{}/merged", &dcontainer_base);
-
-                    chroot(&PathBuf::from(&dcontainer_base_merged))?;
-                    chdir("/")?;
-                    mount(
-                        Some("proc"),
-                        "/proc",
-                        Some("proc"),
-                        MsFlags::empty(),
-                        None::<&Path>,
-                    )
-                    .map_err(|why| Error::MountFailed(why))?;
-
-                    // execでプログラムを実行
-                    use std::os::unix::process::CommandExt;
-                    std::process::Command::new(launch.cmd().main())
-                        .args(launch.cmd().detail())
-                        .exec();
+                    // execに失敗しても、injeshの処理には戻らずに終了する
+                    let run = || -> Result<(), Box<dyn std::error::Error>> {
+                        terminal.attach()?;
+
+                        let mut command = match sideload {
+                            // デバック対象のマウントをそのまま使い、デバックコンテナをマウントした場所以下のプログラムを実行
+                            Some(ref sideload) => sideload.command(launch.cmd())?,
+                            None => {
+                                terminal.bind_devpts()?;
+                                common::chroot_dir(&merged)?;
+                                mount(
+                                    Some("proc"),
+                                    "/proc",
+                                    Some("proc"),
+                                    MsFlags::empty(),
+                                    None::<&Path>,
+                                )
+                                .map_err(|why| Error::MountFailed(why))?;
+
+                                let mut command = std::process::Command::new(launch.cmd().main());
+                                command.args(launch.cmd().detail());
+                                command
+                            }
+                        };
+                        command.env(common::SESSION_ENV, launch.name());
+                        // 最後にデバック対象と同じ権限に落とす
+                        if let Some(ref security) = security {
+                            security.apply()?;
+                        }
+
+                        // execでプログラムを実行
+                        Err(session::exec(&mut command, launch.cmd().main()))?
+                    };
+                    session::exit_child(run())
                 }
-                Err(_) => return Err(Error::Fork)?,
+                Err(_) => Err(Error::Fork)?,
             }
-        };
+        }
+    }
+
+    /// デバックコンテナの設定ファイル、ディレクトリを作成し、rootfsを挿入したoverlayfsをマウントする
+    ///
+    /// デバックコンテナ内には入らない。`watch`からも用いる
+    pub fn prepare<DO: Downloader, RW: setting::Reader + setting::Writer>(
+        &self,
+        launch: &mut command::Launch<DO, RW>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // デバックコンテナの設定ファイル、ディレクトリ周りの初期化
+        initialize_setting(launch)?;
+
+        rootfs_injected_overlayfs_mount(launch)?;
 
         Ok(())
     }
@@ -148,23 +203,51 @@ fn initialize_setting<DO: Downloader, RW: setting::Reader + setting::Writer>(
     create_dir_all(format!("{}/worker", &dcontainer_base))?;
 
     let target_container_id = launch.target_container().container_id().to_string();
+    let target_runtime = launch.target_container().runtime().clone();
+    let target_fs_view = launch.target_container().fs_view();
+    let target_selector = launch.target_container().selector().cloned();
+    let target_name = launch
+        .target_container()
+        .name()
+        .map(|name| name.to_string());
+    let target_pid = launch.target_container().pid();
+    let target_start_time = launch.target_container().start_time()?;
+    let namespaces = launch.namespaces().to_vec();
+    let mount_namespace = launch.mount_namespace();
+    let cgroup = launch.cgroup();
+    let match_security = launch.match_security();
     launch
         .setting_handler_mut()
         .init(&target_container_id, setting::Shell::Bash, &[]);
+    let setting = launch.setting_handler_mut().read_mut()?;
+    setting.set_runtime(target_runtime);
+    setting.set_fs_view(target_fs_view);
+    if let Some(selector) = target_selector {
+        setting.set_selector(selector);
+    }
+    if let Some(target_name) = target_name {
+        setting.set_target_name(&target_name);
+    }
+    setting.set_process(target_pid, target_start_time);
+    setting.set_namespaces(namespaces);
+    setting.set_mount_namespace(mount_namespace);
+    if let Some(cgroup) = cgroup {
+        setting.set_cgroup(cgroup);
+    }
+    setting.set_match_security(match_security);
     launch.setting_handler().write()?;
 
     Ok(())
 }
 
-/// rootfsを挿入したoverlayfsをマウントする
-/// mountpoint: `~/.injesh/containers/<CONTAINER_NAME>/merged`
-fn rootfs_injected_overlayfs_mount<DO: Downloader, RW: setting::Reader + setting::Writer>(
-    launch: &command::Launch<DO, RW>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let user = user::User::new()?;
-    let dcontainer_base = format!("{}/{}", user.containers(), launch.name());
-
-    let rootfs_path = match launch.rootfs_option() {
+/// デバックコンテナに挿入するrootfsのパスを返す
+///
+/// イメージを指定した場合、最新でなければダウンロードする
+pub fn rootfs_path<DO: Downloader>(
+    rootfs_option: &RootFSOption<DO>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let rootfs_path = match rootfs_option {
+        RootFSOption::Rootfs(path) => path.clone(),
         RootFSOption::RootfsImage(image) => {
             match image.check_rootfs_newest() {
                 Ok(is_newest) => {
@@ -181,31 +264,30 @@ fn rootfs_injected_overlayfs_mount<DO: Downloader, RW: setting::Reader + setting
         _ => Err(Error::NotImplemented)?,
     };
 
-    let dcontainer_merged = PathBuf::from(format!("{}/merged", &dcontainer_base));
-    let dcontainer_worker = format!("{}/worker", &dcontainer_base);
-    let dcontainer_upper = format!("{}/upper", &dcontainer_base);
+    Ok(rootfs_path)
+}
 
-    let target_container_merged = launch
-        .target_container()
-        .mergeddir()
-        .to_str()
-        .ok_or(Error::NonValidUnicode)?;
-
-    let mount_data = format!(
-        "lowerdir={}:{},upperdir={},workdir={}",
-        rootfs_path.to_str().ok_or(Error::InvalidRootFSPath)?,
-        target_container_merged,
-        dcontainer_upper,
-        dcontainer_worker
-    );
-    mount(
-        Some("overlay"),
-        &dcontainer_merged,
-        Some("overlay"),
-        MsFlags::empty(),
-        Some(mount_data.as_str()),
-    )
-    .map_err(|why| Error::MountFailed(why))?;
+/// rootfsを挿入したoverlayfsをマウントする
+/// mountpoint: `~/.injesh/containers/<CONTAINER_NAME>/merged`
+fn rootfs_injected_overlayfs_mount<DO: Downloader, RW: setting::Reader + setting::Writer>(
+    launch: &mut command::Launch<DO, RW>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let user = user::User::new()?;
+    let dcontainer_base = PathBuf::from(format!("{}/{}", user.containers(), launch.name()));
+
+    let rootfs_path = rootfs_path(launch.rootfs_option())?;
+    common::mount_debug_overlay(
+        &dcontainer_base,
+        &rootfs_path,
+        launch.target_container().target_root(),
+    )?;
+
+    // デバック対象コンテナの再起動後にexecで再マウントするため、rootfsのパスを保存する
+    launch
+        .setting_handler_mut()
+        .read_mut()?
+        .set_rootfs(&rootfs_path);
+    launch.setting_handler().write()?;
 
     Ok(())
 }
@@ -0,0 +1,238 @@
+use crate::cmd::{common, exec};
+use crate::command::{self, ns_error::Error};
+use crate::docker_client::DockerClient;
+use crate::namespace::{self, IdMap};
+use crate::setting::{self, Reader};
+use crate::{container, setting_yaml, user, utils};
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// 表示するnamespaceの種類。`/proc/<PID>/ns`以下のファイル名
+const NS_TYPES: [&str; 8] = ["cgroup", "ipc", "mnt", "net", "pid", "time", "user", "uts"];
+
+#[derive(Serialize)]
+struct Report {
+    /// デバック対象を直接指定した場合は`None`
+    debug_container: Option<String>,
+    target_pid: u32,
+    /// 実行中のセッションのうち、最も古いプロセス
+    session_pid: Option<u32>,
+    namespaces: Vec<NsRow>,
+    uid_map: IdMaps,
+    gid_map: IdMaps,
+}
+
+#[derive(Serialize)]
+struct NsRow {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    host: Option<u64>,
+    target: Option<u64>,
+    session: Option<u64>,
+    /// セッションがデバック対象と共有しているか。セッションが無い場合は、execで入るかどうか
+    shared: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct IdMaps {
+    target: Vec<IdMap>,
+    session: Option<Vec<IdMap>>,
+}
+
+pub struct NsStruct;
+
+impl NsStruct {
+    /// ホスト、デバック対象、デバックセッションのnamespaceを比較して表示する
+    ///
+    /// デバックコンテナの名前が指定された場合は、`injesh exec`と同様にデバック対象を選択し、
+    /// 実行中のセッションを`common::SESSION_ENV`から探す。
+    /// それ以外はDockerコンテナのIDか名前として扱い、ホストとデバック対象のみを比較する
+    pub fn ns(&self, ns: &command::Ns) -> Result<(), Box<dyn std::error::Error>> {
+        utils::check_initialized()?;
+
+        let user = user::User::new()?;
+        let setting_path = Path::new(user.containers())
+            .join(ns.name())
+            .join("setting.yaml");
+        let (setting, container) = match setting_path.exists() {
+            true => {
+                let setting = setting_yaml::YamlReaderWriter::new(&setting_path).read()?;
+                let container = exec::resolve_target(&setting)?;
+                (Some(setting), container)
+            }
+            false => {
+                let container = DockerClient::from_env()
+                    .and_then(|mut client| container::Container::new(&mut client, ns.name()))
+                    .map_err(|_| Error::NotFound(ns.name().to_string()))?;
+                (None, container)
+            }
+        };
+
+        let target_pid = container.pid();
+        let session_pid = setting.as_ref().and_then(|_| find_session(ns.name()));
+        let namespaces = NS_TYPES
+            .iter()
+            .map(|kind| {
+                let host = namespace::inode(None, kind).ok();
+                let target = namespace::inode(Some(target_pid), kind).ok();
+                let session = session_pid.and_then(|pid| namespace::inode(Some(pid), kind).ok());
+                // inode番号はnsfsのデバイスごとのため、デバイス番号と合わせて比べる
+                let identity = |pid| namespace::identity(pid, kind).ok();
+                let target_identity = identity(Some(target_pid));
+                let shared = match (session_pid, &setting) {
+                    (Some(session_pid), _) => Some(
+                        target_identity.is_some() && identity(Some(session_pid)) == target_identity,
+                    ),
+                    (None, Some(setting)) => {
+                        Some(joins(setting, kind, identity(None) == target_identity))
+                    }
+                    (None, None) => None,
+                };
+                NsRow {
+                    kind,
+                    host,
+                    target,
+                    session,
+                    shared,
+                }
+            })
+            .collect();
+        let id_maps = |file: &str| -> Result<IdMaps, Box<dyn std::error::Error>> {
+            Ok(IdMaps {
+                target: namespace::id_map(target_pid, file)?,
+                session: session_pid.and_then(|pid| namespace::id_map(pid, file).ok()),
+            })
+        };
+
+        let report = Report {
+            debug_container: setting.as_ref().map(|_| ns.name().to_string()),
+            target_pid,
+            session_pid,
+            namespaces,
+            uid_map: id_maps("uid_map")?,
+            gid_map: id_maps("gid_map")?,
+        };
+        match ns.json() {
+            true => println!("{}", serde_json::to_string_pretty(&report)?),
+            false => print_report(&report),
+        }
+
+        Ok(())
+    }
+
+    pub fn new() -> NsStruct {
+        NsStruct
+    }
+}
+
+impl Default for NsStruct {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// セッションが無い場合に、`injesh exec`でデバック対象のnamespaceに入るかどうか
+fn joins(setting: &setting::Setting, kind: &str, same_user_as_host: bool) -> bool {
+    match kind {
+        "mnt" => setting.mount_namespace(),
+        // ホストと異なる場合のみ入る
+        "user" => !same_user_as_host,
+        _ => setting
+            .namespaces()
+            .iter()
+            .any(|joined| joined.to_string() == kind),
+    }
+}
+
+/// デバックコンテナ`name`のセッションのうち、最も古いプロセスを探す
+fn find_session(name: &str) -> Option<u32> {
+    let entry = format!("{}={}", common::SESSION_ENV, name);
+
+    fs::read_dir("/proc")
+        .ok()?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| match fs::read(format!("/proc/{}/environ", pid)) {
+            Ok(environ) => has_entry(&environ, &entry),
+            Err(_) => false,
+        })
+        .min_by_key(|pid| container::start_time(*pid).unwrap_or(u64::MAX))
+}
+
+/// NUL区切りの`environ`に`entry`が含まれるかどうか
+fn has_entry(environ: &[u8], entry: &str) -> bool {
+    environ
+        .split(|byte| *byte == 0)
+        .any(|variable| variable == entry.as_bytes())
+}
+
+fn print_report(report: &Report) {
+    let inode = |inode: Option<u64>| inode.map_or("-".to_string(), |inode| inode.to_string());
+
+    println!("target: PID {}", report.target_pid);
+    if let Some(ref name) = report.debug_container {
+        match report.session_pid {
+            Some(pid) => println!("session: {} (PID {})", name, pid),
+            None => println!(
+                "session: {} is not running (SHARED shows what injesh exec would join)",
+                name
+            ),
+        }
+    }
+
+    println!();
+    println!(
+        "{:<10}{:>12}{:>12}{:>12}  SHARED",
+        "NAMESPACE", "HOST", "TARGET", "SESSION"
+    );
+    for row in &report.namespaces {
+        let shared = match row.shared {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "-",
+        };
+        println!(
+            "{:<10}{:>12}{:>12}{:>12}  {}",
+            row.kind,
+            inode(row.host),
+            inode(row.target),
+            inode(row.session),
+            shared
+        );
+    }
+
+    for (file, id_maps) in [("uid_map", &report.uid_map), ("gid_map", &report.gid_map)] {
+        println!();
+        println!(
+            "{:<10}{:>12}{:>12}{:>12}",
+            file, "INSIDE", "OUTSIDE", "COUNT"
+        );
+        let session = id_maps.session.iter().flatten().map(|map| ("session", map));
+        for (owner, map) in id_maps
+            .target
+            .iter()
+            .map(|map| ("target", map))
+            .chain(session)
+        {
+            println!(
+                "{:<10}{:>12}{:>12}{:>12}",
+                owner, map.inside, map.outside, map.count
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_entry() {
+        let environ = b"PATH=/bin\0INJESH_CONTAINER=web-debug\0HOME=/root\0";
+        assert!(has_entry(environ, "INJESH_CONTAINER=web-debug"));
+        assert!(!has_entry(environ, "INJESH_CONTAINER=web"));
+        assert!(!has_entry(b"", "INJESH_CONTAINER=web-debug"));
+    }
+}
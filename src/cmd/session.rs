@@ -0,0 +1,142 @@
+use crate::cmd::sideload;
+
+use nix::errno::Errno;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::{error, fmt};
+
+/// `docker exec`と同様の終了コード。injesh自身のエラーでコマンドを実行できなかった
+pub const EXIT_FAILED: i32 = 125;
+/// コマンドは存在するが実行できなかった
+pub const EXIT_CANNOT_EXECUTE: i32 = 126;
+/// コマンドが見つからなかった
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+/// 子プロセスに転送するシグナル
+const FORWARDED_SIGNALS: [Signal; 4] = [
+    Signal::SIGINT,
+    Signal::SIGTERM,
+    Signal::SIGHUP,
+    Signal::SIGQUIT,
+];
+
+/// シグナルを転送する子プロセス
+static FORWARD_PID: AtomicI32 = AtomicI32::new(0);
+/// カーネルから送られたシグナルも転送するかどうか
+static FORWARD_ALL: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum Error {
+    ExecFailed(String, Errno),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ExecFailed(cmd, why) => {
+                write!(f, "cmd::session: failed to execute {}: {}", cmd, why)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// `command`をexecする。戻った場合は失敗のため、`cmd`とerrnoを持つエラーを返す
+pub fn exec(command: &mut Command, cmd: &str) -> Error {
+    let why = command.exec();
+    Error::ExecFailed(
+        cmd.to_string(),
+        Errno::from_i32(why.raw_os_error().unwrap_or(0)),
+    )
+}
+
+/// forkした子プロセスで、execに至るまでの処理の結果を受け取って終了する
+///
+/// execに失敗した場合は、injeshの処理に戻らずに`docker exec`と同様の終了コードで終了する
+pub fn exit_child(result: Result<(), Box<dyn std::error::Error>>) -> ! {
+    let code = match result {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("injesh: {}", e);
+            match (
+                e.downcast_ref::<Error>(),
+                e.downcast_ref::<sideload::Error>(),
+            ) {
+                (Some(Error::ExecFailed(_, Errno::ENOENT)), _)
+                | (_, Some(sideload::Error::CommandNotFound(_))) => EXIT_NOT_FOUND,
+                (Some(Error::ExecFailed(..)), _) => EXIT_CANNOT_EXECUTE,
+                _ => EXIT_FAILED,
+            }
+        }
+    };
+
+    // 親プロセスから引き継いだバッファなどを書き出さずに終了する
+    unsafe { libc::_exit(code) }
+}
+
+/// 親プロセスが受け取ったSIGINT、SIGTERM、SIGHUP、SIGQUITを子プロセス`child`に転送する
+///
+/// 子プロセスが同じ端末のセッションにいる場合、端末からのシグナルは子プロセスも直接受け取るため、
+/// `all`が`false`の場合はkillなどで他のプロセスから送られたものだけを転送する
+pub fn forward_signals(child: Pid, all: bool) -> Result<(), Errno> {
+    FORWARD_PID.store(child.as_raw(), Ordering::SeqCst);
+    FORWARD_ALL.store(all, Ordering::SeqCst);
+    let action = SigAction::new(
+        SigHandler::SigAction(forward_signal),
+        SaFlags::SA_RESTART | SaFlags::SA_SIGINFO,
+        SigSet::empty(),
+    );
+    for signal in FORWARDED_SIGNALS {
+        unsafe { sigaction(signal, &action) }?;
+    }
+
+    Ok(())
+}
+
+extern "C" fn forward_signal(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _: *mut libc::c_void,
+) {
+    // SI_USER、SI_QUEUE、SI_TKILLなど、プロセスから送られたシグナルのsi_codeは0以下
+    let from_process = unsafe { (*info).si_code } <= 0;
+    if from_process || FORWARD_ALL.load(Ordering::SeqCst) {
+        unsafe { libc::kill(FORWARD_PID.load(Ordering::SeqCst), signal) };
+    }
+}
+
+/// 子プロセスの終了状態を、シェルと同様の終了コードにする
+///
+/// シグナルで終了した場合は128+シグナル番号
+pub fn exit_code(status: WaitStatus) -> i32 {
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        _ => EXIT_FAILED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        let pid = Pid::from_raw(1);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 0)), 0);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 7)), 7);
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGINT, false)),
+            130
+        );
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGKILL, true)),
+            137
+        );
+    }
+}
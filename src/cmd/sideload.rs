@@ -0,0 +1,407 @@
+use crate::command::Cmd;
+
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use nix::sched::{unshare, CloneFlags};
+use nix::unistd::chdir;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{error, fmt};
+
+/// デバック対象のmount namespace内で、デバックコンテナのルートファイルシステムをマウントする場所の候補
+///
+/// デバック対象のファイルシステムには書き込まず、最初に見つかった空のディレクトリに重ねてマウントする
+pub const MOUNT_POINTS: [&str; 5] = ["/mnt", "/media", "/srv", "/opt", "/home"];
+
+/// `MOUNT_POINTS`が無い場合(distrolessなど)に、tmpfsを重ねてマウントする場所を作るディレクトリの候補
+///
+/// tmpfsはデバックセッションのmount namespaceにのみ見え、元の中身はデバックセッションから隠れる
+const TMPFS_MOUNT_POINTS: [&str; 3] = ["/tmp", "/run", "/var/tmp"];
+
+/// tmpfs内、またはデバック対象のルートに作成するマウントする場所の名前
+const HIDDEN_DIR: &str = ".injesh";
+
+/// マウントした場所以下からコマンドを探すディレクトリ
+const BIN_DIRS: [&str; 6] = [
+    "/usr/local/sbin",
+    "/usr/local/bin",
+    "/usr/sbin",
+    "/usr/bin",
+    "/sbin",
+    "/bin",
+];
+
+// <linux/mount.h>
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x4;
+
+const PT_INTERP: u32 = 3;
+
+#[derive(Debug)]
+pub enum Error {
+    OpenTreeFailed(Errno),
+    MountPointNotFound,
+    MoveMountFailed(PathBuf, Errno),
+    MountTmpfsFailed(PathBuf, Errno),
+    NotAttached,
+    CommandNotFound(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OpenTreeFailed(why) => write!(
+                f,
+                "cmd::sideload: failed to clone the debug container (Linux 5.2 or later is required): {}",
+                why
+            ),
+            Error::MountPointNotFound => write!(
+                f,
+                "cmd::sideload: none of {} is an empty directory in the target, none of {} exists and / is not writable",
+                MOUNT_POINTS.join(", "),
+                TMPFS_MOUNT_POINTS.join(", ")
+            ),
+            Error::MoveMountFailed(mount_point, why) => write!(
+                f,
+                "cmd::sideload: failed to mount the debug container on {}: {}",
+                mount_point.display(),
+                why
+            ),
+            Error::MountTmpfsFailed(dir, why) => write!(
+                f,
+                "cmd::sideload: failed to mount tmpfs on {}: {}",
+                dir.display(),
+                why
+            ),
+            Error::NotAttached => write!(
+                f,
+                "cmd::sideload: the debug container is not mounted in the target"
+            ),
+            Error::CommandNotFound(cmd) => write!(
+                f,
+                "cmd::sideload: {} is not found in the debug container",
+                cmd
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// デバック対象のmount namespaceに持ち込む、デバックコンテナのルートファイルシステム
+///
+/// setnsの後はホストのパスを参照できないため、事前にどこにも属さないマウントとして複製しておく
+pub struct SideLoad {
+    tree: File,
+    mount_point: Option<PathBuf>,
+}
+
+impl SideLoad {
+    /// `merged`を複製する。デバック対象のnamespaceに入る前に呼ぶ
+    pub fn new(merged: &Path) -> Result<SideLoad, Box<dyn std::error::Error>> {
+        let path = CString::new(merged.as_os_str().as_bytes())?;
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint | AT_RECURSIVE,
+            )
+        };
+        if fd < 0 {
+            Err(Error::OpenTreeFailed(Errno::last()))?
+        }
+
+        Ok(SideLoad {
+            tree: unsafe { File::from_raw_fd(fd as RawFd) },
+            mount_point: None,
+        })
+    }
+
+    /// デバック対象のmount namespaceに入った後に呼び、複製を`mount_point`で決めた場所にマウントする
+    ///
+    /// mount namespaceを複製してからマウントするため、デバック対象のプロセスからは見えない
+    pub fn attach(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        unshare(CloneFlags::CLONE_NEWNS)?;
+        // デバック対象のマウントの変更は受け取り、こちらの変更は伝搬させない
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_SLAVE,
+            None::<&str>,
+        )?;
+        let mount_point = mount_point()?;
+
+        let empty = CString::new("")?;
+        let path = CString::new(mount_point.as_os_str().as_bytes())?;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_move_mount,
+                self.tree.as_raw_fd(),
+                empty.as_ptr(),
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            )
+        };
+        if ret < 0 {
+            Err(Error::MoveMountFailed(mount_point.clone(), Errno::last()))?
+        }
+        chdir("/")?;
+        self.mount_point = Some(mount_point);
+
+        Ok(())
+    }
+
+    /// `cmd`をマウントした場所以下のプログラムとして実行する`Command`を作成する
+    ///
+    /// デバック対象には無いデバックコンテナの動的ローダーを直接起動し、ライブラリもマウントした場所以下から読み込む。
+    /// `PATH`と`LD_LIBRARY_PATH`はマウントした場所以下を優先する
+    pub fn command(&self, cmd: &Cmd) -> Result<Command, Box<dyn std::error::Error>> {
+        let root = self.mount_point.as_deref().ok_or(Error::NotAttached)?;
+        let program = find_program(root, cmd.main())?;
+        let library_path = library_dirs(root)
+            .iter()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(":");
+        let search_path = BIN_DIRS
+            .iter()
+            .map(|dir| format!("{}{}", root.display(), dir))
+            .chain(BIN_DIRS.iter().map(|dir| dir.to_string()))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let mut command = match elf_interpreter(&fs::read(&program)?) {
+            Some(interpreter) => {
+                let mut command = Command::new(format!("{}{}", root.display(), interpreter));
+                command
+                    .arg("--library-path")
+                    .arg(&library_path)
+                    .arg(&program);
+                command
+            }
+            None => Command::new(&program),
+        };
+        command
+            .args(cmd.detail())
+            .env("PATH", search_path)
+            .env("LD_LIBRARY_PATH", library_path);
+
+        Ok(command)
+    }
+}
+
+/// 複製をマウントする場所を決める
+///
+/// 1. `MOUNT_POINTS`のうち空のディレクトリ
+/// 2. `TMPFS_MOUNT_POINTS`のうち存在するディレクトリにtmpfsをマウントし、その中に作成した`.injesh`
+/// 3. デバック対象のルートに作成した`/.injesh` (scratchイメージなど。デバック対象のファイルシステムに残る)
+///
+/// 1と2はデバック対象のファイルシステムに書き込まないため、読み込み専用でもよい
+fn mount_point() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if let Some(dir) = MOUNT_POINTS
+        .iter()
+        .map(PathBuf::from)
+        .find(|dir| is_empty_dir(dir))
+    {
+        return Ok(dir);
+    }
+
+    if let Some(dir) = TMPFS_MOUNT_POINTS
+        .iter()
+        .map(PathBuf::from)
+        .find(|dir| fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir()))
+    {
+        mount(
+            Some("tmpfs"),
+            &dir,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some("mode=0755"),
+        )
+        .map_err(|why| Error::MountTmpfsFailed(dir.clone(), why))?;
+        let mount_point = dir.join(HIDDEN_DIR);
+        fs::create_dir(&mount_point)?;
+        return Ok(mount_point);
+    }
+
+    Ok(hidden_dir(Path::new("/")).ok_or(Error::MountPointNotFound)?)
+}
+
+/// `root`以下の`.injesh`を作成する。以前に作成した空のディレクトリはそのまま使う
+fn hidden_dir(root: &Path) -> Option<PathBuf> {
+    let dir = root.join(HIDDEN_DIR);
+    match fs::create_dir(&dir) {
+        Ok(_) => Some(dir),
+        Err(_) if is_empty_dir(&dir) => Some(dir),
+        Err(_) => None,
+    }
+}
+
+/// デバック対象が使っていない、中身の無いディレクトリかどうか
+///
+/// 重ねてマウントしても、デバック対象のファイルが隠れることはない
+fn is_empty_dir(dir: &Path) -> bool {
+    let is_dir = fs::symlink_metadata(dir).is_ok_and(|metadata| metadata.is_dir());
+    is_dir && fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+/// `root`以下から`cmd`を探す
+fn find_program(root: &Path, cmd: &str) -> Result<PathBuf, Error> {
+    let candidates: Vec<PathBuf> = match cmd.contains('/') {
+        true => vec![root.join(cmd.trim_start_matches('/'))],
+        false => BIN_DIRS
+            .iter()
+            .map(|dir| root.join(dir.trim_start_matches('/')).join(cmd))
+            .collect(),
+    };
+
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| Error::CommandNotFound(cmd.to_string()))
+}
+
+/// `root`以下に存在するライブラリのディレクトリ
+fn library_dirs(root: &Path) -> Vec<PathBuf> {
+    let multiarch = format!("{}-linux-gnu", std::env::consts::ARCH);
+    [
+        "/lib".to_string(),
+        "/lib64".to_string(),
+        format!("/lib/{}", multiarch),
+        "/usr/lib".to_string(),
+        "/usr/lib64".to_string(),
+        format!("/usr/lib/{}", multiarch),
+    ]
+    .iter()
+    .map(|dir| root.join(dir.trim_start_matches('/')))
+    .filter(|dir| dir.is_dir())
+    .collect()
+}
+
+/// 64bitリトルエンディアンのELFから、動的ローダー(PT_INTERP)のパスを取り出す
+///
+/// 静的リンクされたプログラムやELF以外、オフセットが壊れている場合は`None`
+fn elf_interpreter(elf: &[u8]) -> Option<String> {
+    let bytes_at = |offset: usize, size: usize| elf.get(offset..offset.checked_add(size)?);
+    let u16_at = |offset: usize| -> Option<usize> {
+        Some(u16::from_le_bytes(bytes_at(offset, 2)?.try_into().ok()?) as usize)
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(bytes_at(offset, 4)?.try_into().ok()?))
+    };
+    let u64_at = |offset: usize| -> Option<usize> {
+        usize::try_from(u64::from_le_bytes(bytes_at(offset, 8)?.try_into().ok()?)).ok()
+    };
+
+    // ELFCLASS64, ELFDATA2LSB
+    if elf.get(..6)? != b"\x7fELF\x02\x01" {
+        return None;
+    }
+    let phoff = u64_at(0x20)?;
+    let phentsize = u16_at(0x36)?;
+    let phnum = u16_at(0x38)?;
+
+    (0..phnum)
+        .map(|i| phoff.checked_add(i.checked_mul(phentsize)?))
+        .take_while(Option::is_some)
+        .flatten()
+        .find(|header| u32_at(*header) == Some(PT_INTERP))
+        .and_then(|header| {
+            let offset = u64_at(header.checked_add(0x08)?)?;
+            let size = u64_at(header.checked_add(0x20)?)?;
+            let interpreter = bytes_at(offset, size)?;
+            let interpreter = interpreter.split(|byte| *byte == 0).next()?;
+            String::from_utf8(interpreter.to_vec()).ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elf_interpreter() {
+        let interpreter = b"/lib64/ld-linux-x86-64.so.2\0";
+        let mut elf = vec![0u8; 0x40 + 0x38 * 2];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+        // PT_LOAD, PT_INTERPの順
+        elf[0x40..0x44].copy_from_slice(&1u32.to_le_bytes());
+        let header = 0x40 + 0x38;
+        elf[header..header + 4].copy_from_slice(&PT_INTERP.to_le_bytes());
+        let offset = elf.len() as u64;
+        elf[header + 0x08..header + 0x10].copy_from_slice(&offset.to_le_bytes());
+        elf[header + 0x20..header + 0x28]
+            .copy_from_slice(&(interpreter.len() as u64).to_le_bytes());
+        elf.extend_from_slice(interpreter);
+
+        assert_eq!(
+            elf_interpreter(&elf),
+            Some("/lib64/ld-linux-x86-64.so.2".to_string())
+        );
+        // 静的リンク
+        elf[header..header + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(elf_interpreter(&elf), None);
+        assert_eq!(elf_interpreter(b"#!/bin/sh\n"), None);
+
+        // 壊れたオフセットやサイズでもオーバーフローしない
+        elf[header..header + 4].copy_from_slice(&PT_INTERP.to_le_bytes());
+        elf[header + 0x20..header + 0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(elf_interpreter(&elf), None);
+        elf[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(elf_interpreter(&elf), None);
+    }
+
+    #[test]
+    fn test_find_program() {
+        let root = std::env::temp_dir().join(format!("injesh-sideload-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/curl"), b"").unwrap();
+        assert!(!is_empty_dir(&root.join("empty")));
+        fs::create_dir(root.join("empty")).unwrap();
+
+        assert!(is_empty_dir(&root.join("empty")));
+        assert!(!is_empty_dir(&root.join("usr/bin")));
+        assert_eq!(
+            find_program(&root, "curl").unwrap(),
+            root.join("usr/bin/curl")
+        );
+        assert_eq!(
+            find_program(&root, "/usr/bin/curl").unwrap(),
+            root.join("usr/bin/curl")
+        );
+        assert!(matches!(
+            find_program(&root, "dig"),
+            Err(Error::CommandNotFound(cmd)) if cmd == "dig"
+        ));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_hidden_dir() {
+        let root = std::env::temp_dir().join(format!("injesh-hidden-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        assert_eq!(hidden_dir(&root), Some(root.join(".injesh")));
+        // 以前に作成したもの
+        assert_eq!(hidden_dir(&root), Some(root.join(".injesh")));
+        fs::write(root.join(".injesh/file"), b"").unwrap();
+        assert_eq!(hidden_dir(&root), None);
+        assert_eq!(hidden_dir(&root.join("missing")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::mount::{mount, MsFlags};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{openpty, Winsize};
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::unistd::{dup2, isatty, pipe2, setsid};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::{error, fmt};
+
+const STDIN: RawFd = 0;
+
+/// SIGWINCHを受け取ったことを中継ループに知らせるパイプの書き込み側
+static SIGWINCH_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+#[derive(Debug)]
+pub enum Error {
+    OpenPty(Errno),
+    ControllingTerminal(Errno),
+    WindowSize(Errno),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::OpenPty(why) => {
+                write!(
+                    f,
+                    "cmd::terminal: failed to open a pseudo-terminal: {}",
+                    why
+                )
+            }
+            Error::ControllingTerminal(why) => write!(
+                f,
+                "cmd::terminal: failed to set the controlling terminal: {}",
+                why
+            ),
+            Error::WindowSize(why) => {
+                write!(f, "cmd::terminal: failed to set the window size: {}", why)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// デバックセッションの標準入出力
+///
+/// `-t`の場合は疑似端末を割り当て、親プロセスがホストの端末との間で入出力を中継する。
+/// `-t`のみの場合、標準入力は`/dev/null`になる。どちらも無い場合は呼び出し元の標準入出力をそのまま使う
+pub struct Terminal {
+    pty: Option<Pty>,
+    null: Option<File>,
+    /// chroot先の`dev/pts`。ホストの`/dev/pts`をbind mountする
+    devpts: Option<File>,
+    interactive: bool,
+}
+
+struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Terminal {
+    /// 親プロセスで、デバック対象のnamespaceに入る前に呼ぶ
+    pub fn open(tty: bool, interactive: bool) -> Result<Terminal, Box<dyn std::error::Error>> {
+        let pty = match tty {
+            true => Some(Pty::open()?),
+            false => None,
+        };
+        let null = match tty && !interactive {
+            true => Some(File::open("/dev/null")?),
+            false => None,
+        };
+
+        Ok(Terminal {
+            pty,
+            null,
+            devpts: None,
+            interactive,
+        })
+    }
+
+    /// chrootするディレクトリ`root`に`dev/pts`を作成して開く
+    ///
+    /// setnsの後はホストのパスを参照できないため、親プロセスで事前に呼ぶ。疑似端末を使わない場合は何もしない
+    pub fn prepare_devpts(&mut self, root: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pty.is_none() {
+            return Ok(());
+        }
+        let devpts = root.join("dev/pts");
+        fs::create_dir_all(&devpts)?;
+        self.devpts = Some(File::open(&devpts)?);
+
+        Ok(())
+    }
+
+    /// 子プロセスで呼び、標準入出力を切り替える
+    ///
+    /// 疑似端末の場合は新しいセッションを作って制御端末にするため、セッション内でジョブ制御が使える
+    pub fn attach(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(ref pty) = self.pty {
+            let slave = pty.slave.as_raw_fd();
+            setsid()?;
+            Errno::result(unsafe { libc::ioctl(slave, libc::TIOCSCTTY, 0) })
+                .map_err(Error::ControllingTerminal)?;
+            for fd in 0..=2 {
+                dup2(slave, fd)?;
+            }
+        }
+        if let Some(ref null) = self.null {
+            dup2(null.as_raw_fd(), STDIN)?;
+        }
+
+        Ok(())
+    }
+
+    /// 子プロセスでchrootする前に呼び、ホストの`/dev/pts`を`prepare_devpts`で開いたディレクトリにbind mountする
+    ///
+    /// chroot後も`tty`などから疑似端末を参照できる。マウントは新しいmount namespace内で行い、ホストには伝搬させない
+    pub fn bind_devpts(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let devpts = match self.devpts {
+            Some(ref devpts) => devpts,
+            None => return Ok(()),
+        };
+        unshare(CloneFlags::CLONE_NEWNS)?;
+        mount(
+            None::<&str>,
+            "/",
+            None::<&str>,
+            MsFlags::MS_REC | MsFlags::MS_SLAVE,
+            None::<&str>,
+        )?;
+        mount(
+            Some("/dev/pts"),
+            format!("/proc/self/fd/{}", devpts.as_raw_fd()).as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )?;
+
+        Ok(())
+    }
+
+    /// 疑似端末を割り当てるかどうか。割り当てた場合、子プロセスは別のセッションで動く
+    pub fn is_pty(&self) -> bool {
+        self.pty.is_some()
+    }
+
+    /// 親プロセスで呼び、疑似端末の入出力を子プロセスが終了するまで中継する
+    ///
+    /// ホストの端末はrawモードにし、SIGWINCHで端末の大きさを疑似端末に伝える。
+    /// 終了時にはホストの端末を元に戻す。疑似端末を使わない場合は何もしない
+    pub fn proxy(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pty {
+            Some(pty) => pty.proxy(self.interactive),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Pty {
+    /// ホストの端末の設定と大きさを引き継いだ疑似端末を開く
+    fn open() -> Result<Pty, Box<dyn std::error::Error>> {
+        let (winsize, termios) = match isatty(STDIN) {
+            Ok(true) => (window_size(STDIN).ok(), termios::tcgetattr(STDIN).ok()),
+            _ => (None, None),
+        };
+        let pty = openpty(winsize.as_ref(), termios.as_ref()).map_err(Error::OpenPty)?;
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        // 実行するコマンドに引き継がない
+        for fd in [&master, &slave] {
+            fcntl(fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+        }
+
+        Ok(Pty { master, slave })
+    }
+
+    fn proxy(self, interactive: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let Pty { master, slave } = self;
+        // 子プロセスが終了した時にmasterの読み込みがEIOになるよう、親の持つslaveは閉じる
+        drop(slave);
+
+        let (winch_read, winch_write) = pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        let (winch_read, winch_write) = unsafe {
+            (
+                File::from_raw_fd(winch_read),
+                File::from_raw_fd(winch_write),
+            )
+        };
+        let _guard = match interactive && isatty(STDIN).unwrap_or(false) {
+            true => Some(HostTerminal::raw(&winch_write)?),
+            false => None,
+        };
+
+        let mut stdin_open = interactive;
+        let mut buf = [0u8; 4096];
+        loop {
+            let mut fds = vec![
+                PollFd::new(master.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(winch_read.as_raw_fd(), PollFlags::POLLIN),
+            ];
+            if stdin_open {
+                fds.push(PollFd::new(STDIN, PollFlags::POLLIN));
+            }
+            match poll(&mut fds, -1) {
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(why) => Err(why)?,
+            }
+            let ready = |fd: &PollFd| fd.revents().is_some_and(|events| !events.is_empty());
+
+            if ready(&fds[1]) {
+                while (&winch_read).read(&mut buf).is_ok_and(|len| len > 0) {}
+                if let Ok(winsize) = window_size(STDIN) {
+                    set_window_size(master.as_raw_fd(), &winsize)?;
+                }
+            }
+            if stdin_open && ready(&fds[2]) {
+                match nix::unistd::read(STDIN, &mut buf) {
+                    Ok(0) | Err(_) => {
+                        stdin_open = false;
+                        // 標準入力の終わりを疑似端末のEOF(Ctrl-D)として伝える
+                        let _ = (&master).write_all(b"\x04");
+                    }
+                    Ok(len) => (&master).write_all(&buf[..len])?,
+                }
+            }
+            if ready(&fds[0]) {
+                match (&master).read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        let mut stdout = io::stdout().lock();
+                        stdout.write_all(&buf[..len])?;
+                        stdout.flush()?;
+                    }
+                    // slaveが全て閉じられた
+                    Err(why) if why.raw_os_error() == Some(libc::EIO) => break,
+                    Err(why) if why.kind() == io::ErrorKind::Interrupted => {}
+                    Err(why) => Err(why)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// rawモードにしたホストの端末。dropすると元の設定に戻す
+struct HostTerminal {
+    termios: Termios,
+    sigwinch: SigAction,
+}
+
+impl HostTerminal {
+    /// ホストの端末をrawモードにし、SIGWINCHを受け取ると`notify`に書き込むようにする
+    fn raw(notify: &File) -> Result<HostTerminal, Box<dyn std::error::Error>> {
+        let termios = termios::tcgetattr(STDIN)?;
+        let mut raw = termios.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(STDIN, SetArg::TCSANOW, &raw)?;
+
+        SIGWINCH_PIPE.store(notify.as_raw_fd(), Ordering::SeqCst);
+        let action = SigAction::new(
+            SigHandler::Handler(on_sigwinch),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        let sigwinch = match unsafe { sigaction(Signal::SIGWINCH, &action) } {
+            Ok(sigwinch) => sigwinch,
+            Err(why) => {
+                let _ = termios::tcsetattr(STDIN, SetArg::TCSANOW, &termios);
+                Err(why)?
+            }
+        };
+
+        Ok(HostTerminal { termios, sigwinch })
+    }
+}
+
+impl Drop for HostTerminal {
+    fn drop(&mut self) {
+        let _ = unsafe { sigaction(Signal::SIGWINCH, &self.sigwinch) };
+        SIGWINCH_PIPE.store(-1, Ordering::SeqCst);
+        let _ = termios::tcsetattr(STDIN, SetArg::TCSADRAIN, &self.termios);
+    }
+}
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    let fd = SIGWINCH_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // シグナルハンドラ内ではwriteのみ行う。パイプが一杯の場合は既に通知済み
+        unsafe { libc::write(fd, [0u8].as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+fn window_size(fd: RawFd) -> Result<Winsize, Error> {
+    let mut winsize = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) })
+        .map_err(Error::WindowSize)?;
+
+    Ok(winsize)
+}
+
+fn set_window_size(fd: RawFd, winsize: &Winsize) -> Result<(), Error> {
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, winsize) })
+        .map_err(Error::WindowSize)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_size() {
+        let pty = Pty::open().unwrap();
+        let winsize = Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        set_window_size(pty.master.as_raw_fd(), &winsize).unwrap();
+
+        let slave = window_size(pty.slave.as_raw_fd()).unwrap();
+        assert_eq!((slave.ws_row, slave.ws_col), (24, 80));
+    }
+}
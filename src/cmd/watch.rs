@@ -0,0 +1,212 @@
+use crate::cmd::common;
+use crate::cmd::launch::{self, LaunchStruct};
+use crate::command::{self, watch_error::Error, Cmd, RootFSOption};
+use crate::docker_client::DockerClient;
+use crate::image_downloader::Downloader;
+use crate::setting::{Reader, Writer};
+use crate::{container, namespace, setting_yaml, user, utils};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 監視するDockerのイベント
+const ACTIONS: [&str; 3] = ["start", "die", "destroy"];
+
+pub struct WatchStruct;
+
+impl WatchStruct {
+    /// Dockerのイベントを監視し、ラベルに一致するコンテナの起動・終了に合わせてデバックコンテナを作成・片付ける
+    ///
+    /// - `start`: 設定されたrootfsでデバックコンテナを作成する(中には入らない)。既存のデバックコンテナは新しい対象に再マウントする
+    /// - `die`, `destroy`: 対象コンテナを指すデバックコンテナをアンマウントし、`--delete`の場合は削除する
+    ///
+    /// 1つのイベントの処理に失敗しても監視は続ける
+    pub fn watch<DO: Downloader>(
+        &self,
+        watch: &command::Watch<DO>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        utils::check_initialized()?;
+
+        // イメージのダウンロードはここで一度だけ行い、各デバックコンテナはそのrootfsを用いる
+        let rootfs = launch::rootfs_path(watch.rootfs_option())?;
+        let user = user::User::new()?;
+
+        // イベントの監視とデバック対象の解決で、同じクライアントを使う
+        let mut client = DockerClient::from_env()?;
+        let events = container::container_events(&mut client, watch.selector().labels(), &ACTIONS)?;
+        println!(
+            "watching containers with {}",
+            watch.selector().labels().join(", ")
+        );
+        for event in events {
+            let event = event?;
+            let id = &event.Actor.ID;
+            let name = event
+                .Actor
+                .Attributes
+                .get("name")
+                .map(String::as_str)
+                .unwrap_or(container::short_id(id));
+
+            let result = match event.Action.as_str() {
+                "start" => launch_debug_container::<DO>(&mut client, &user, &rootfs, id, name),
+                _ => tear_down_debug_containers(&user, id, watch.delete()),
+            };
+            if let Err(e) = result {
+                eprintln!("{} {}: {}", event.Action, name, e);
+            }
+        }
+
+        Err(Error::EventsClosed)?
+    }
+
+    pub fn new() -> WatchStruct {
+        WatchStruct
+    }
+}
+
+fn launch_debug_container<DO: Downloader>(
+    client: &mut DockerClient,
+    user: &user::User,
+    rootfs: &Path,
+    id: &str,
+    container_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target_container = container::Container::new(client, id)?;
+
+    // 再起動した場合などは既存のデバックコンテナを使い続ける
+    if let Some(name) = find_debug_containers(Path::new(user.containers()), id).first() {
+        remount_debug_container(user, rootfs, &target_container, name)?;
+        println!(
+            "start {}: debug container {} already exists, remounted",
+            container_name, name
+        );
+        return Ok(());
+    }
+
+    let name = utils::generate_name(user, container_name);
+    let setting_file_path = PathBuf::from(format!("{}/{}/setting.yaml", user.containers(), name));
+    let mut launch = command::Launch::<DO, _>::new(
+        target_container,
+        RootFSOption::Rootfs(rootfs.to_path_buf()),
+        name,
+        Cmd::new(Box::new(std::iter::empty())),
+        namespace::DEFAULT_KINDS.to_vec(),
+        false,
+        None,
+        false,
+        false,
+        false,
+        setting_yaml::YamlReaderWriter::new(&setting_file_path),
+    )?;
+    LaunchStruct::new().prepare(&mut launch)?;
+    println!("start {}: launched {}", container_name, launch.name());
+
+    Ok(())
+}
+
+/// `die`でアンマウントしたデバックコンテナを、起動し直したデバック対象のルートファイルシステムで張り直す
+///
+/// launch時のrootfsが記録されていない場合は、監視で用いているrootfsを使う
+fn remount_debug_container(
+    user: &user::User,
+    rootfs: &Path,
+    target_container: &container::Container,
+    name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dcontainer_base = Path::new(user.containers()).join(name);
+    let reader_writer = setting_yaml::YamlReaderWriter::new(&dcontainer_base.join("setting.yaml"));
+    let mut setting = reader_writer.read()?;
+    let rootfs = setting.rootfs().unwrap_or(rootfs).to_path_buf();
+
+    common::unmount_debug_overlay(&dcontainer_base)?;
+    common::mount_debug_overlay(&dcontainer_base, &rootfs, target_container.target_root())?;
+
+    setting.set_process(target_container.pid(), target_container.start_time()?);
+    setting.set_fs_view(target_container.fs_view());
+    reader_writer.write(&setting)?;
+
+    Ok(())
+}
+
+fn tear_down_debug_containers(
+    user: &user::User,
+    id: &str,
+    delete: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for name in find_debug_containers(Path::new(user.containers()), id) {
+        let dcontainer_base = Path::new(user.containers()).join(&name);
+
+        common::unmount_debug_overlay(&dcontainer_base)?;
+        if delete {
+            fs::remove_dir_all(&dcontainer_base)
+                .map_err(command::delete_error::Error::RemoveFailed)?;
+            println!("deleted {}", name);
+        } else {
+            println!("unmounted {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+/// デバック対象がDockerコンテナ`id`であるデバックコンテナの名前を返す
+fn find_debug_containers(containers_dir: &Path, id: &str) -> Vec<String> {
+    let entries = match fs::read_dir(containers_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| {
+            let reader = setting_yaml::YamlReaderWriter::new(&entry.path().join("setting.yaml"));
+            match reader.read() {
+                Ok(setting) => {
+                    *setting.runtime() == container::Runtime::Docker
+                        && setting.docker_container_id() == id
+                }
+                Err(_) => false,
+            }
+        })
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    names.sort();
+
+    names
+}
+
+impl Default for WatchStruct {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::setting::{self, Writer};
+
+    #[test]
+    fn test_find_debug_containers() {
+        let containers_dir =
+            std::env::temp_dir().join(format!("injesh-watch-{}", std::process::id()));
+        let write = |name: &str, id: &str, runtime: container::Runtime| {
+            fs::create_dir_all(containers_dir.join(name)).unwrap();
+            let mut setting = setting::Setting::new(id, setting::Shell::Bash, &[]);
+            setting.set_runtime(runtime);
+            setting_yaml::YamlReaderWriter::new(&containers_dir.join(name).join("setting.yaml"))
+                .write(&setting)
+                .unwrap();
+        };
+        write("web-debug-2", "abcd", container::Runtime::Docker);
+        write("web-debug", "abcd", container::Runtime::Docker);
+        write("db-debug", "efgh", container::Runtime::Docker);
+        write("podman-debug", "abcd", container::Runtime::Podman);
+
+        let names = find_debug_containers(&containers_dir, "abcd");
+        fs::remove_dir_all(&containers_dir).unwrap();
+
+        assert_eq!(names, vec!["web-debug", "web-debug-2"]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{container, image, image_downloader, setting, user};
+use crate::{cgroup, container, image, image_downloader, namespace, setting, user};
 use std::fmt;
 use std::marker::PhantomData;
 use std::path::PathBuf;
@@ -33,6 +33,8 @@ where
     List(List),
     Delete(Delete),
     File(FileSubCommand),
+    Watch(Watch<D>),
+    Ns(Ns),
 }
 
 #[derive(Debug)]
@@ -95,6 +97,12 @@ where
     rootfs_option: RootFSOption<D>,
     name: String,
     cmd: Cmd,
+    namespaces: Vec<namespace::Kind>,
+    mount_namespace: bool,
+    cgroup: Option<cgroup::Placement>,
+    match_security: bool,
+    tty: bool,
+    interactive: bool,
     setting_handler: setting::SettingHandler<RW>,
 }
 
@@ -136,11 +144,18 @@ where
     D: image_downloader::Downloader,
     RW: setting::Reader + setting::Writer,
 {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         target_container: container::Container,
         rootfs_option: RootFSOption<D>,
         name: String,
         cmd: Cmd,
+        namespaces: Vec<namespace::Kind>,
+        mount_namespace: bool,
+        cgroup: Option<cgroup::Placement>,
+        match_security: bool,
+        tty: bool,
+        interactive: bool,
         setting_reader_writer: RW,
     ) -> Result<Launch<D, RW>, Box<dyn std::error::Error>> {
         let setting_handler = setting::SettingHandler::new(setting_reader_writer);
@@ -150,6 +165,12 @@ where
             rootfs_option: rootfs_option,
             name: name,
             cmd: cmd,
+            namespaces,
+            mount_namespace,
+            cgroup,
+            match_security,
+            tty,
+            interactive,
             setting_handler: setting_handler,
         })
     }
@@ -174,6 +195,36 @@ where
         &self.cmd
     }
 
+    /// デバック対象コンテナと共有するnamespace
+    pub fn namespaces(&self) -> &[namespace::Kind] {
+        &self.namespaces
+    }
+
+    /// デバック対象のmount namespaceに入るかどうか
+    pub fn mount_namespace(&self) -> bool {
+        self.mount_namespace
+    }
+
+    /// デバックセッションを置くcgroup
+    pub fn cgroup(&self) -> Option<cgroup::Placement> {
+        self.cgroup
+    }
+
+    /// デバック対象と同じセキュリティコンテキストでコマンドを実行するかどうか
+    pub fn match_security(&self) -> bool {
+        self.match_security
+    }
+
+    /// 疑似端末を割り当てるかどうか
+    pub fn tty(&self) -> bool {
+        self.tty
+    }
+
+    /// 標準入力を渡すかどうか
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
     pub fn setting_handler(&self) -> &setting::SettingHandler<RW> {
         &self.setting_handler
     }
@@ -238,6 +289,11 @@ where
 {
     name: String,
     cmd: Cmd,
+    namespaces: namespace::Selection,
+    cgroup: Option<cgroup::Placement>,
+    match_security: bool,
+    tty: bool,
+    interactive: bool,
     setting_handler: setting::SettingHandler<RW>,
     phantom_downloader: PhantomData<D>,
 }
@@ -266,12 +322,27 @@ where
     D: image_downloader::Downloader,
     RW: setting::Reader + setting::Writer,
 {
-    pub fn new(name: String, cmd: Cmd, setting_reader_writer: RW) -> Exec<D, RW> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        name: String,
+        cmd: Cmd,
+        namespaces: namespace::Selection,
+        cgroup: Option<cgroup::Placement>,
+        match_security: bool,
+        tty: bool,
+        interactive: bool,
+        setting_reader_writer: RW,
+    ) -> Exec<D, RW> {
         let setting_handler = setting::SettingHandler::new(setting_reader_writer);
 
         Exec {
             name: name,
             cmd: cmd,
+            namespaces,
+            cgroup,
+            match_security,
+            tty,
+            interactive,
             setting_handler: setting_handler,
             phantom_downloader: PhantomData,
         }
@@ -285,6 +356,32 @@ where
         &self.cmd
     }
 
+    /// `--ns`、`--no-ns`で指定された、設定ファイルのnamespaceに対する変更
+    pub fn namespaces(&self) -> &namespace::Selection {
+        &self.namespaces
+    }
+
+    /// デバックセッションを置くcgroup。`None`の場合はlaunch時の指定に従う
+    pub fn cgroup(&self) -> Option<cgroup::Placement> {
+        self.cgroup
+    }
+
+    /// デバック対象と同じセキュリティコンテキストでコマンドを実行するかどうか。
+    /// `false`の場合はlaunch時の指定に従う
+    pub fn match_security(&self) -> bool {
+        self.match_security
+    }
+
+    /// 疑似端末を割り当てるかどうか
+    pub fn tty(&self) -> bool {
+        self.tty
+    }
+
+    /// 標準入力を渡すかどうか
+    pub fn interactive(&self) -> bool {
+        self.interactive
+    }
+
     pub fn setting(&self) -> &setting::SettingHandler<RW> {
         &self.setting_handler
     }
@@ -342,6 +439,113 @@ impl Delete {
     }
 }
 
+#[derive(Debug)]
+pub struct Ns {
+    name: String,
+    json: bool,
+}
+
+pub mod ns_error {
+    use std::fmt;
+
+    #[derive(Debug)]
+    pub enum Error {
+        NotFound(String),
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::NotFound(name) => write!(
+                    f,
+                    "{} is neither a debug container nor a running container",
+                    name
+                ),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+}
+
+impl Ns {
+    pub fn new(name: String, json: bool) -> Ns {
+        Ns { name, json }
+    }
+
+    /// デバックコンテナの名前、またはデバック対象コンテナのIDか名前
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 表ではなくJSONで出力するか
+    pub fn json(&self) -> bool {
+        self.json
+    }
+}
+
+#[derive(Debug)]
+pub struct Watch<D>
+where
+    D: image_downloader::Downloader,
+{
+    selector: container::Selector,
+    rootfs_option: RootFSOption<D>,
+    delete: bool,
+}
+
+pub mod watch_error {
+    use std::fmt;
+
+    #[derive(Debug)]
+    pub enum Error {
+        RootfsNotFound,
+        EventsClosed,
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Error::RootfsNotFound => write!(f, "rootfs of the debug containers not specified"),
+                Error::EventsClosed => write!(f, "docker closed the event stream"),
+            }
+        }
+    }
+
+    impl std::error::Error for Error {}
+}
+
+impl<D> Watch<D>
+where
+    D: image_downloader::Downloader,
+{
+    pub fn new(
+        selector: container::Selector,
+        rootfs_option: RootFSOption<D>,
+        delete: bool,
+    ) -> Watch<D> {
+        Watch {
+            selector,
+            rootfs_option,
+            delete,
+        }
+    }
+
+    /// 監視対象のコンテナを選択するラベル
+    pub fn selector(&self) -> &container::Selector {
+        &self.selector
+    }
+
+    pub fn rootfs_option(&self) -> &RootFSOption<D> {
+        &self.rootfs_option
+    }
+
+    /// デバック対象コンテナの終了時に、アンマウントに加えてデバックコンテナを削除するか
+    pub fn delete(&self) -> bool {
+        self.delete
+    }
+}
+
 #[derive(Debug)]
 pub enum FileSubCommand {
     Pull(File),
@@ -1,70 +1,278 @@
-use form_urlencoded;
-use httparse::{Response, EMPTY_HEADER};
+use crate::docker_client::DockerClient;
+use crate::{
+    container_containerd, container_cri, container_lxd, container_machined, container_podman,
+    namespace, user,
+};
 use serde::Deserialize;
 use serde_yaml;
-use std::io::prelude::*;
-use std::os::unix::net::UnixStream;
-use std::{error, fmt, path};
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::{error, fmt, fs, path};
 // debug
 // use std::collections::HashMap;
 // use std::ffi::OsStr;
 
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const COMPOSE_NUMBER_LABEL: &str = "com.docker.compose.container-number";
+
+// The libpod API returns the same shapes for the fields used here,
+// so these are shared with `container_podman`.
 #[derive(Debug, Deserialize)]
-struct DockerListApiResponse {
-    containers: Vec<DockerContainerList>,
+#[allow(non_snake_case)]
+pub(crate) struct DockerContainerList {
+    pub(crate) Id: String,
+    #[serde(default)]
+    pub(crate) Names: Vec<String>,
+    #[serde(default)]
+    pub(crate) Labels: HashMap<String, String>,
+    #[serde(default)]
+    pub(crate) Image: String,
+    /// e.g. `Up 2 hours (healthy)`
+    #[serde(default)]
+    pub(crate) Status: String,
+    #[serde(default)]
+    pub(crate) Ports: Vec<DockerPort>,
 }
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct DockerContainerList {
-    Id: String,
+pub(crate) struct DockerPort {
+    pub(crate) IP: Option<String>,
+    pub(crate) PrivatePort: u16,
+    pub(crate) PublicPort: Option<u16>,
+    pub(crate) Type: String,
 }
 
+/// An entry of `GET /events`
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct DockerInspectApiResponse {
-    containers: DockerContainerInspect,
+pub(crate) struct DockerEvent {
+    /// e.g. `start`, `die`, `destroy`
+    pub(crate) Action: String,
+    pub(crate) Actor: DockerEventActor,
 }
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct DockerContainerInspect {
-    GraphDriver: DockerGraphDriver,
+pub(crate) struct DockerEventActor {
+    pub(crate) ID: String,
+    /// the container's labels, plus `name` and `image`
+    #[serde(default)]
+    pub(crate) Attributes: HashMap<String, String>,
 }
+
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
-struct DockerGraphDriver {
-    Name: String,
-    Data: DockerGraphDriverData,
+pub(crate) struct DockerContainerInspect {
+    pub(crate) Id: String,
+    /// with a leading slash, e.g. `/web`
+    #[serde(default)]
+    pub(crate) Name: String,
+    pub(crate) State: DockerContainerState,
+    pub(crate) GraphDriver: DockerGraphDriver,
 }
 #[derive(Debug, Deserialize)]
 #[allow(non_snake_case)]
+pub(crate) struct DockerContainerState {
+    pub(crate) Status: String,
+    pub(crate) Running: bool,
+    pub(crate) Paused: bool,
+    pub(crate) Pid: u32,
+}
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub(crate) struct DockerGraphDriver {
+    pub(crate) Name: String,
+    /// `null` or other keys for drivers other than overlay2, e.g. vfs and btrfs
+    #[serde(default)]
+    pub(crate) Data: Option<DockerGraphDriverData>,
+}
+// zfs and btrfs report keys of their own, so every field may be missing
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+#[allow(non_snake_case)]
 pub struct DockerGraphDriverData {
-    LowerDir: path::PathBuf,
-    UpperDir: path::PathBuf,
-    MergedDir: path::PathBuf,
-    WorkDir: path::PathBuf,
+    pub(crate) LowerDir: path::PathBuf,
+    pub(crate) UpperDir: path::PathBuf,
+    pub(crate) MergedDir: path::PathBuf,
+    pub(crate) WorkDir: path::PathBuf,
+}
+
+/// Container runtime which manages the target container
+#[derive(Clone, Debug, PartialEq)]
+pub enum Runtime {
+    Docker,
+    Podman,
+    /// containerd namespace such as `default` or `k8s.io`
+    Containerd(String),
+    /// any runtime behind a Kubernetes CRI socket
+    Cri,
+    /// a bare process, the container ID is its PID
+    Pid,
+    /// the main process of a cgroup, the container ID is the cgroup path
+    Cgroup,
+    /// an LXD system container, the container ID is its name
+    Lxd,
+    /// a machine registered with systemd-machined, the container ID is its name
+    Machined,
+}
+
+impl Runtime {
+    pub fn containerd_namespace(&self) -> Option<&str> {
+        match self {
+            Runtime::Containerd(namespace) => Some(namespace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Runtime::Docker => write!(f, "docker"),
+            Runtime::Podman => write!(f, "podman"),
+            Runtime::Containerd(_) => write!(f, "containerd"),
+            Runtime::Cri => write!(f, "cri"),
+            Runtime::Pid => write!(f, "pid"),
+            Runtime::Cgroup => write!(f, "cgroup"),
+            Runtime::Lxd => write!(f, "lxd"),
+            Runtime::Machined => write!(f, "machined"),
+        }
+    }
+}
+
+impl std::str::FromStr for Runtime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "docker" => Ok(Runtime::Docker),
+            "podman" => Ok(Runtime::Podman),
+            "containerd" => Ok(Runtime::Containerd(
+                container_containerd::DEFAULT_NAMESPACE.to_string(),
+            )),
+            "cri" => Ok(Runtime::Cri),
+            "pid" => Ok(Runtime::Pid),
+            "cgroup" => Ok(Runtime::Cgroup),
+            "lxd" => Ok(Runtime::Lxd),
+            "machined" => Ok(Runtime::Machined),
+            _ => Err(Error::UnknownRuntime(s.to_string())),
+        }
+    }
+}
+
+/// How the target's root filesystem is reached from the host
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsView {
+    /// a directory the runtime reports, such as overlay2's `MergedDir`
+    MergedDir,
+    /// `/proc/{pid}/root`, which works with any runtime and storage driver
+    ProcRoot,
+    /// the target's root overlaps the debug containers, e.g. it is the host's `/`,
+    /// so only the rootfs is used as overlayfs refuses overlapping layers
+    RootfsOnly,
+}
+
+impl fmt::Display for FsView {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsView::MergedDir => write!(f, "merged-dir"),
+            FsView::ProcRoot => write!(f, "proc-root"),
+            FsView::RootfsOnly => write!(f, "rootfs-only"),
+        }
+    }
+}
+
+impl std::str::FromStr for FsView {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merged-dir" => Ok(FsView::MergedDir),
+            "proc-root" => Ok(FsView::ProcRoot),
+            "rootfs-only" => Ok(FsView::RootfsOnly),
+            _ => Err(Error::UnknownFsView(s.to_string())),
+        }
+    }
+}
+
+/// Docker containers selected by labels instead of a name or ID,
+/// e.g. a service of a compose project
+///
+/// When several containers match, `replica` picks one of them.
+/// Without it the selection is refused, listing the candidates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    /// `key=value`
+    labels: Vec<String>,
+    /// 1-based, in the order of the compose container number, then the name
+    replica: Option<usize>,
+}
+
+impl Selector {
+    pub fn new(labels: Vec<String>, replica: Option<usize>) -> Selector {
+        Selector { labels, replica }
+    }
+    /// Selects a compose service, or any container of the project without `service`.
+    pub fn compose(
+        project: &str,
+        service: Option<&str>,
+        mut labels: Vec<String>,
+        replica: Option<usize>,
+    ) -> Selector {
+        labels.push(format!("{}={}", COMPOSE_PROJECT_LABEL, project));
+        if let Some(service) = service {
+            labels.push(format!("{}={}", COMPOSE_SERVICE_LABEL, service));
+        }
+        Selector { labels, replica }
+    }
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+    pub fn replica(&self) -> Option<usize> {
+        self.replica
+    }
 }
 
 #[derive(Debug)]
 pub struct Container {
+    runtime: Runtime,
     container_id: String,
     pid: u32,
-    lowerdir: path::PathBuf,
-    upperdir: path::PathBuf,
+    // only known when the runtime tells us its overlay layers
+    lowerdir: Option<path::PathBuf>,
+    upperdir: Option<path::PathBuf>,
+    /// the target's root filesystem as seen from the host
     mergeddir: path::PathBuf,
-    workdir: path::PathBuf,
+    workdir: Option<path::PathBuf>,
+    fs_view: FsView,
+    // set when the target was selected by labels, so that it can be selected again
+    selector: Option<Selector>,
+    // the Docker container name, which survives a recreation unlike the ID
+    name: Option<String>,
+    // start time of `pid` when the target was resolved
+    start_time: Option<u64>,
 }
 
 #[derive(Debug)]
 pub enum Error {
     NotInitialized,
     InvalidPid,
-    InvalidParameter,
-    InvalidResponse,
     ContainerNotFound,
-    GraphDriverNotOverlay2,
+    AmbiguousContainer(Vec<String>),
+    InvalidLabel(String),
+    SeveralReplicas(Vec<String>),
+    ReplicaNotFound(usize),
     GraphDriverPathNotFound,
-    ApiResponseError(String),
     ContainerProcessNotFound,
+    ContainerNotRunning(String),
+    ContainerPaused,
+    UnknownRuntime(String),
+    UnknownFsView(String),
+    ProcessNotFound(u32),
+    ProcessReplaced(u32),
+    CgroupNotFound(path::PathBuf),
+    CgroupEmpty(path::PathBuf),
 }
 
 impl fmt::Display for Error {
@@ -72,15 +280,37 @@ impl fmt::Display for Error {
         match self {
             Error::NotInitialized => write!(f, "Not initialized"),
             Error::InvalidPid => write!(f, "Invalid PID"),
-            Error::InvalidParameter => write!(f, "Invalid Parameter"),
-            Error::InvalidResponse => write!(f, "Invalid Response"),
+            Error::InvalidLabel(label) => write!(f, "label {} is not key=value", label),
+            Error::SeveralReplicas(candidates) => write!(
+                f,
+                "several containers match, pick one with --replica: {}",
+                candidates.join(", ")
+            ),
+            Error::ReplicaNotFound(replica) => write!(f, "replica {} not found", replica),
             Error::ContainerNotFound => write!(f, "Container Not Found"),
-            Error::GraphDriverNotOverlay2 => write!(f, "GraphDriver is not overlay2"),
+            Error::AmbiguousContainer(candidates) => write!(
+                f,
+                "container name or ID prefix is ambiguous, candidates: {}",
+                candidates.join(", ")
+            ),
             Error::GraphDriverPathNotFound => write!(f, "GraphDriver path not found"),
-            Error::ApiResponseError(message) => {
-                write!(f, "API response error with message: {}", message)
-            }
             Error::ContainerProcessNotFound => write!(f, "container process not found"),
+            Error::ContainerNotRunning(status) => {
+                write!(f, "container is not running (status: {})", status)
+            }
+            Error::ContainerPaused => write!(f, "container is paused, unpause it first"),
+            Error::UnknownRuntime(runtime) => write!(f, "unknown container runtime: {}", runtime),
+            Error::UnknownFsView(fs_view) => write!(f, "unknown filesystem view: {}", fs_view),
+            Error::ProcessNotFound(pid) => write!(f, "process {} not found", pid),
+            Error::CgroupNotFound(cgroup) => write!(f, "cgroup {} not found", cgroup.display()),
+            Error::CgroupEmpty(cgroup) => {
+                write!(f, "cgroup {} has no processes", cgroup.display())
+            }
+            Error::ProcessReplaced(pid) => write!(
+                f,
+                "target process has exited and PID {} now belongs to another process",
+                pid
+            ),
         }
     }
 }
@@ -88,94 +318,275 @@ impl fmt::Display for Error {
 impl error::Error for Error {}
 
 impl Container {
-    pub fn new(name_or_id: &str) -> Result<Container, Box<dyn std::error::Error>> {
-        let id = name_or_id;
-        let pid = match get_pid_from_container_id(id) {
-            Ok(pid) => {
-                // println!("\n\nYeah it's ID. {}\n\n", pid);
-                pid
-            }
-            Err(_) => {
-                // println!("\n\n\nnot id.\n\n\n");
-                let may_name = name_or_id;
-                let id = Self::convert_name_to_id(may_name)?;
-                get_pid_from_container_id(&id)?
+    pub fn new(
+        client: &mut DockerClient,
+        name_or_id: &str,
+    ) -> Result<Container, Box<dyn std::error::Error>> {
+        let id = Self::convert_name_to_id(client, name_or_id)?;
+        let inspect = inspect_container(client, &id)?;
+        let pid = pid_from_state(&inspect.Id, &inspect.State)?;
+
+        // btrfs, zfs, vfs and the containerd snapshotters have no MergedDir to stack on
+        let mut container = match (inspect.GraphDriver.Name.as_str(), inspect.GraphDriver.Data) {
+            ("overlay2", Some(data)) => {
+                Self::from_graph_driver(Runtime::Docker, &inspect.Id, pid, data)
             }
+            _ => Self::from_pid(Runtime::Docker, &inspect.Id, pid),
         };
-        // println!("------------");
-        let docker_info: DockerInspectApiResponse = serde_json::from_str(&request_docker_api(
-            "GET",
-            &format!("/containers/{id}/json", id = id),
-            None,
-        )?)?;
-        // println!("\n\ndockerinfo: ```{:?}```", docker_info);
-        if docker_info.containers.GraphDriver.Name != "overlay2" {
-            Err(Error::GraphDriverNotOverlay2)?
+        container.name = Some(inspect.Name.trim_start_matches('/').to_string());
+
+        Ok(container)
+    }
+
+    /// Resolves the running Docker container which matches all the labels of the selector.
+    pub fn from_selector(
+        client: &mut DockerClient,
+        selector: &Selector,
+    ) -> Result<Container, Box<dyn std::error::Error>> {
+        if let Some(label) = selector.labels().iter().find(|label| !label.contains('=')) {
+            Err(Error::InvalidLabel(label.to_string()))?
         }
-        let graph_driver_data = docker_info.containers.GraphDriver.Data;
+        let filters = serde_json::json!({ "label": selector.labels() });
+        let containers: Vec<DockerContainerList> =
+            client.get("/containers/json", &[("filters", &filters.to_string())])?;
+
+        let id = select_replica(containers, selector.replica())?;
+        let mut container = Self::new(client, &id)?;
+        container.selector = Some(selector.clone());
 
-        Ok(Container {
-            container_id: id.to_string(),
+        Ok(container)
+    }
+
+    /// Resolves the target through the API of the given runtime.
+    pub fn with_runtime(
+        runtime: &Runtime,
+        name_or_id: &str,
+    ) -> Result<Container, Box<dyn std::error::Error>> {
+        match runtime {
+            Runtime::Docker => Self::new(&mut DockerClient::from_env()?, name_or_id),
+            Runtime::Podman => container_podman::new(name_or_id),
+            Runtime::Containerd(namespace) => container_containerd::new(namespace, name_or_id),
+            Runtime::Cri => container_cri::from_id(name_or_id),
+            Runtime::Pid => Self::from_process(name_or_id.parse().map_err(|_| Error::InvalidPid)?),
+            Runtime::Cgroup => Self::from_cgroup(name_or_id),
+            Runtime::Lxd => container_lxd::new(name_or_id),
+            Runtime::Machined => container_machined::new(name_or_id),
+        }
+    }
+
+    /// Targets the main process of a cgroup v2 directory,
+    /// e.g. a systemd unit or a container whose runtime socket is unreachable.
+    ///
+    /// Both `/sys/fs/cgroup/system.slice/foo.service` and `/system.slice/foo.service`
+    /// (as in `/proc/{pid}/cgroup`) are accepted.
+    pub fn from_cgroup(cgroup: &str) -> Result<Container, Box<dyn std::error::Error>> {
+        let mut cgroup_path = path::PathBuf::from(cgroup);
+        if !cgroup_path.starts_with(CGROUP_ROOT) {
+            cgroup_path = path::Path::new(CGROUP_ROOT).join(cgroup.trim_start_matches('/'));
+        }
+        let pid = get_pid_from_cgroup(&cgroup_path)?;
+
+        Ok(Self::from_pid(
+            Runtime::Cgroup,
+            &cgroup_path.to_string_lossy(),
             pid,
-            lowerdir: graph_driver_data.LowerDir,
-            upperdir: graph_driver_data.UpperDir,
+        ))
+    }
+
+    /// Targets a process which no supported runtime manages,
+    /// such as a systemd service with `PrivateNetwork`.
+    pub fn from_process(pid: u32) -> Result<Container, Box<dyn std::error::Error>> {
+        if !path::Path::new(&format!("/proc/{pid}", pid = pid)).exists() {
+            Err(Error::ProcessNotFound(pid))?
+        }
+
+        Ok(Self::from_pid(Runtime::Pid, &pid.to_string(), pid))
+    }
+
+    pub(crate) fn from_graph_driver(
+        runtime: Runtime,
+        container_id: &str,
+        pid: u32,
+        graph_driver_data: DockerGraphDriverData,
+    ) -> Container {
+        let mut container = Container {
+            runtime,
+            container_id: container_id.to_string(),
+            pid,
+            lowerdir: Some(graph_driver_data.LowerDir),
+            upperdir: Some(graph_driver_data.UpperDir),
             mergeddir: graph_driver_data.MergedDir,
-            workdir: graph_driver_data.WorkDir,
-        })
+            workdir: Some(graph_driver_data.WorkDir),
+            fs_view: FsView::MergedDir,
+            selector: None,
+            name: None,
+            start_time: start_time(pid).ok(),
+        };
+        container.check_root();
+        container
+    }
+
+    /// For runtimes which only tell the PID.
+    /// The target's filesystem is reached through `/proc/{pid}/root`.
+    pub(crate) fn from_pid(runtime: Runtime, container_id: &str, pid: u32) -> Container {
+        let mut container = Self::from_root(runtime, container_id, pid, path::PathBuf::new());
+        container.use_proc_root();
+        container
+    }
+
+    /// For runtimes which tell the PID and the root directory on the host.
+    pub(crate) fn from_root(
+        runtime: Runtime,
+        container_id: &str,
+        pid: u32,
+        root: path::PathBuf,
+    ) -> Container {
+        let mut container = Container {
+            runtime,
+            container_id: container_id.to_string(),
+            pid,
+            lowerdir: None,
+            upperdir: None,
+            mergeddir: root,
+            workdir: None,
+            fs_view: FsView::MergedDir,
+            selector: None,
+            name: None,
+            start_time: start_time(pid).ok(),
+        };
+        container.check_root();
+        container
+    }
+
+    /// Switches to `/proc/{pid}/root` as the view of the target's filesystem.
+    ///
+    /// The overlay layers of the runtime are forgotten,
+    /// as they are of no use without the MergedDir.
+    /// A process in the host's mount namespace has the host's `/` as its root,
+    /// which cannot be stacked under the debug container.
+    pub fn use_proc_root(&mut self) {
+        self.lowerdir = None;
+        self.upperdir = None;
+        self.workdir = None;
+        self.mergeddir = path::PathBuf::from(format!("/proc/{pid}/root", pid = self.pid));
+        self.fs_view = FsView::ProcRoot;
+        self.check_root();
+    }
+
+    /// Falls back to `FsView::RootfsOnly` when the target's root cannot be stacked
+    /// under the debug containers.
+    ///
+    /// That is the case when the target is in the host's mount namespace,
+    /// or its root otherwise contains `~/.injesh` or lies inside it.
+    fn check_root(&mut self) {
+        let shares_mount_namespace = self.fs_view == FsView::ProcRoot
+            && namespace::is_same_namespace(self.pid, "mnt").unwrap_or(false);
+        let overlaps_injesh_home = user::User::new()
+            .is_ok_and(|user| overlaps(&self.mergeddir, path::Path::new(user.injesh_home())));
+        if shares_mount_namespace || overlaps_injesh_home {
+            self.fs_view = FsView::RootfsOnly;
+        }
+    }
+    pub fn fs_view(&self) -> FsView {
+        self.fs_view
+    }
+    /// The target's root to stack under the rootfs, `None` with `FsView::RootfsOnly`
+    pub fn target_root(&self) -> Option<&path::Path> {
+        match self.fs_view {
+            FsView::RootfsOnly => None,
+            _ => Some(&self.mergeddir),
+        }
+    }
+    pub fn selector(&self) -> Option<&Selector> {
+        self.selector.as_ref()
+    }
+    /// Name of a Docker container, without the leading slash
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
     }
     pub fn pid(&self) -> u32 {
         self.pid
     }
+    /// Start time of the target process, in clock ticks after boot,
+    /// as it was when the target was resolved.
+    ///
+    /// Together with the PID, this identifies the process even after the PID is reused.
+    /// Fails when the process had already exited at that time.
+    pub fn start_time(&self) -> Result<u64, Error> {
+        self.start_time.ok_or(Error::ProcessNotFound(self.pid))
+    }
+    /// Fails when the target process is not the one which started at `expected`.
+    pub fn check_start_time(&self, expected: u64) -> Result<(), Box<dyn std::error::Error>> {
+        if self.start_time()? != expected {
+            Err(Error::ProcessReplaced(self.pid))?
+        }
+
+        Ok(())
+    }
     pub fn update_pid(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
-        self.pid = get_pid_from_container_id(self.container_id())?;
+        let fs_view = self.fs_view;
+        match self.runtime {
+            Runtime::Docker => {
+                let inspect =
+                    inspect_container(&mut DockerClient::from_env()?, self.container_id())?;
+                self.pid = pid_from_state(&inspect.Id, &inspect.State)?;
+                self.start_time = start_time(self.pid).ok();
+            }
+            // the root view may depend on the PID, so resolve everything again
+            _ => *self = Self::with_runtime(&self.runtime, self.container_id())?,
+        };
+        // `/proc/{pid}/root` follows the new PID
+        if fs_view != FsView::MergedDir {
+            self.use_proc_root();
+        }
         Ok(self.pid)
     }
-    pub fn lowerdir(&self) -> &std::path::PathBuf {
-        &self.lowerdir
+    pub fn lowerdir(&self) -> Option<&std::path::PathBuf> {
+        self.lowerdir.as_ref()
     }
     pub fn mergeddir(&self) -> &std::path::PathBuf {
         &self.mergeddir
     }
-    pub fn upperdir(&self) -> &std::path::PathBuf {
-        &self.upperdir
+    pub fn upperdir(&self) -> Option<&std::path::PathBuf> {
+        self.upperdir.as_ref()
     }
-    pub fn workdir(&self) -> &std::path::PathBuf {
-        &self.workdir
+    pub fn workdir(&self) -> Option<&std::path::PathBuf> {
+        self.workdir.as_ref()
     }
     pub fn container_id(&self) -> &str {
         &self.container_id
     }
-    pub fn convert_name_to_id(name: &str) -> Result<String, Box<dyn std::error::Error>> {
-        // as commandline:
-        // curl --unix-socket /var/run/docker.sock -X GET "http://localhost/containers/json?all=true&filters=$( python3 -c 'import urllib.parse; print( urllib.parse.quote("""{"name": ["beautiful_curran"]}""") )' )"
-        let docker_info: DockerListApiResponse = serde_json::from_str(&request_docker_api(
-            "GET",
-            "/containers/json",
-            Some(&format!(r#"all=true&filters={{"name": ["{}"]}}"#, name)),
-        )?)?;
-
-        if docker_info.containers.is_empty() {
-            Err(Error::ContainerNotFound)?
-        }
-        if docker_info.containers.len() > 1 {
-            Err(Error::ContainerNotFound)?
-        }
+    /// Resolves a name, full ID or ID prefix to the full 64-character ID.
+    ///
+    /// The name filter of the API is a substring match, so every container is listed
+    /// and matched here in the order: exact name, full ID, unique ID prefix.
+    pub fn convert_name_to_id(
+        client: &mut DockerClient,
+        name_or_id: &str,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let containers: Vec<DockerContainerList> =
+            client.get("/containers/json", &[("all", "true")])?;
 
-        let mut id = docker_info.containers[0].Id.to_string();
-        id.truncate(12);
-        Ok(id)
+        Ok(select_container_id(&containers, name_or_id)?)
     }
 
-    pub fn restart(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn restart(&self, client: &mut DockerClient) -> Result<(), Box<dyn std::error::Error>> {
         let id = self.container_id();
-        request_docker_api("POST", &format!("/containers/{id}/restart", id = id), None)?;
+        client.post(&format!("/containers/{id}/restart", id = id), &[])?;
 
         Ok(())
     }
 
-    pub fn restart_from_name(name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let id = Self::convert_name_to_id(name)?;
-        request_docker_api("POST", &format!("/containers/{id}/restart", id = id), None)?;
+    pub fn restart_from_name(
+        client: &mut DockerClient,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let id = Self::convert_name_to_id(client, name)?;
+        client.post(&format!("/containers/{id}/restart", id = id), &[])?;
 
         Ok(())
     }
@@ -204,6 +615,72 @@ pub struct SettingsYaml {
     pub commands: Vec<String>,
 }
 
+fn inspect_container(
+    client: &mut DockerClient,
+    name_or_id: &str,
+) -> Result<DockerContainerInspect, Box<dyn std::error::Error>> {
+    client.get(&format!("/containers/{id}/json", id = name_or_id), &[])
+}
+
+/// Whether one of `root` and `dir` is at or below the other.
+///
+/// Directories are compared by device and inode,
+/// as `root` may be a link such as `/proc/{pid}/root`.
+fn overlaps(root: &path::Path, dir: &path::Path) -> bool {
+    let is_below = |upper: &path::Path, lower: &path::Path| match fs::metadata(upper) {
+        Ok(upper) => lower.ancestors().any(|ancestor| {
+            fs::metadata(ancestor).is_ok_and(|ancestor| {
+                ancestor.dev() == upper.dev() && ancestor.ino() == upper.ino()
+            })
+        }),
+        Err(_) => false,
+    };
+    let root_path = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+
+    is_below(root, dir) || is_below(dir, &root_path)
+}
+
+/// Picks the container's init PID out of `State` of the inspect response.
+///
+/// `State.Pid` is trusted only when that process really belongs to the container,
+/// which is not the case when injesh runs in another PID namespace than dockerd.
+/// Otherwise the slow `/proc` scan is used.
+fn pid_from_state(
+    container_id: &str,
+    state: &DockerContainerState,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    check_running(state)?;
+
+    if state.Pid != 0 && pid_belongs_to_container(state.Pid, container_id) {
+        return Ok(state.Pid);
+    }
+
+    get_pid_from_container_id(container_id)
+}
+
+/// Rejects targets that have no process we could share namespaces with.
+pub(crate) fn check_running(state: &DockerContainerState) -> Result<(), Error> {
+    if state.Paused {
+        Err(Error::ContainerPaused)?
+    }
+    if !state.Running {
+        Err(Error::ContainerNotRunning(state.Status.clone()))?
+    }
+
+    Ok(())
+}
+
+/// Whether `/proc/{pid}/cgroup` points into the cgroup of the container.
+///
+/// Both the cgroupfs (`/docker/<ID>`) and the systemd (`docker-<ID>.scope`)
+/// drivers embed the full container ID in the path, and so does Podman (`libpod-<ID>.scope`).
+pub(crate) fn pid_belongs_to_container(pid: u32, container_id: &str) -> bool {
+    match std::fs::read_to_string(format!("/proc/{pid}/cgroup", pid = pid)) {
+        Ok(cgroup) => cgroup.contains(container_id),
+        Err(_) => false,
+    }
+}
+
 fn get_pid_from_container_id(target_container_id: &str) -> Result<u32, Box<dyn std::error::Error>> {
     let pid_list = std::fs::read_dir("/proc")?;
     // filter only pid (excluding /proc/uptime .. etc.)
@@ -232,6 +709,158 @@ fn get_pid_from_container_id(target_container_id: &str) -> Result<u32, Box<dyn s
     Ok(pid)
 }
 
+/// Lists the running Docker containers.
+pub(crate) fn list_running_containers(
+    client: &mut DockerClient,
+) -> Result<Vec<DockerContainerList>, Box<dyn std::error::Error>> {
+    client.get("/containers/json", &[])
+}
+
+/// Follows the lifecycle events of the Docker containers which have all the labels.
+///
+/// The iterator blocks until dockerd sends the next event, and never ends on its own.
+pub(crate) fn container_events(
+    client: &mut DockerClient,
+    labels: &[String],
+    actions: &[&str],
+) -> Result<impl Iterator<Item = Result<DockerEvent, serde_json::Error>>, Box<dyn std::error::Error>>
+{
+    if let Some(label) = labels.iter().find(|label| !label.contains('=')) {
+        Err(Error::InvalidLabel(label.to_string()))?
+    }
+    let filters = serde_json::json!({
+        "type": ["container"],
+        "event": actions,
+        "label": labels,
+    });
+    let events = client.stream("/events", &[("filters", &filters.to_string())])?;
+
+    Ok(serde_json::Deserializer::from_reader(events).into_iter())
+}
+
+/// Shortens an ID to 12 characters as `docker ps` does.
+pub(crate) fn short_id(id: &str) -> &str {
+    &id[..12.min(id.len())]
+}
+
+pub(crate) fn select_container_id(
+    containers: &[DockerContainerList],
+    name_or_id: &str,
+) -> Result<String, Error> {
+    // names are listed with a leading slash, e.g. `/web`
+    let exact_name = containers.iter().find(|container| {
+        container
+            .Names
+            .iter()
+            .any(|name| name.trim_start_matches('/') == name_or_id)
+    });
+    if let Some(container) = exact_name {
+        return Ok(container.Id.clone());
+    }
+    if let Some(container) = containers.iter().find(|c| c.Id == name_or_id) {
+        return Ok(container.Id.clone());
+    }
+
+    let candidates: Vec<&DockerContainerList> = containers
+        .iter()
+        .filter(|container| !name_or_id.is_empty() && container.Id.starts_with(name_or_id))
+        .collect();
+    match candidates.as_slice() {
+        [] => Err(Error::ContainerNotFound),
+        [container] => Ok(container.Id.clone()),
+        _ => Err(Error::AmbiguousContainer(
+            candidates
+                .iter()
+                .map(|container| match container.Names.first() {
+                    Some(name) => {
+                        format!(
+                            "{} ({})",
+                            short_id(&container.Id),
+                            name.trim_start_matches('/')
+                        )
+                    }
+                    None => short_id(&container.Id).to_string(),
+                })
+                .collect(),
+        )),
+    }
+}
+
+fn select_replica(
+    mut containers: Vec<DockerContainerList>,
+    replica: Option<usize>,
+) -> Result<String, Error> {
+    // compose names replicas `<project>-<service>-<number>`, which do not sort numerically
+    containers.sort_by_key(|container| {
+        let number = container
+            .Labels
+            .get(COMPOSE_NUMBER_LABEL)
+            .and_then(|number| number.parse::<usize>().ok());
+        (number, container.Names.first().cloned())
+    });
+
+    match (replica, containers.len()) {
+        (_, 0) => Err(Error::ContainerNotFound),
+        (None, 1) => Ok(containers.remove(0).Id),
+        (None, _) => Err(Error::SeveralReplicas(
+            containers
+                .iter()
+                .enumerate()
+                .map(|(index, container)| {
+                    let name = container.Names.first().map_or("", |name| name.as_str());
+                    format!("{}: {}", index + 1, name.trim_start_matches('/'))
+                })
+                .collect(),
+        )),
+        (Some(replica), len) if replica == 0 || replica > len => {
+            Err(Error::ReplicaNotFound(replica))
+        }
+        (Some(replica), _) => Ok(containers.remove(replica - 1).Id),
+    }
+}
+
+/// Picks the oldest process in the cgroup and its descendants.
+///
+/// The oldest one is the main process for systemd services,
+/// and the init process for containers.
+pub(crate) fn get_pid_from_cgroup(cgroup: &path::Path) -> Result<u32, Box<dyn std::error::Error>> {
+    if !cgroup.join("cgroup.procs").exists() {
+        Err(Error::CgroupNotFound(cgroup.to_path_buf()))?
+    }
+
+    let mut pid_list = Vec::new();
+    collect_cgroup_pids(cgroup, &mut pid_list)?;
+
+    // processes may exit while we look at them
+    pid_list
+        .into_iter()
+        .filter_map(|pid| Some((start_time(pid).ok()?, pid)))
+        .min()
+        .map(|(_, pid)| pid)
+        .ok_or_else(|| Error::CgroupEmpty(cgroup.to_path_buf()).into())
+}
+
+fn collect_cgroup_pids(
+    cgroup: &path::Path,
+    pid_list: &mut Vec<u32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let procs = std::fs::read_to_string(cgroup.join("cgroup.procs"))?;
+    pid_list.extend(
+        procs
+            .lines()
+            .filter_map(|pid| pid.trim().parse::<u32>().ok()),
+    );
+
+    for entry in std::fs::read_dir(cgroup)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            collect_cgroup_pids(&entry.path(), pid_list)?;
+        }
+    }
+
+    Ok(())
+}
+
 fn search_pid_linear(
     pid_list: Vec<u32>,
     container_id: &str,
@@ -266,14 +895,7 @@ fn search_pid_linear(
 ///
 /// error: Err
 fn valid_pid_is_container(pid: u32) -> Result<bool, Box<dyn std::error::Error>> {
-    // `/proc/{pid}/cmdline` contains many arguments.
-    let pid_string = std::fs::read_to_string(&format!("/proc/{pid}/stat", pid = pid))?;
-    // the parent process id is the forth argument.
-    let parent_pid = pid_string
-        .split_whitespace()
-        .nth(3)
-        .ok_or(Error::InvalidPid)?
-        .parse::<u32>()?;
+    let parent_pid = parent_pid(pid)?;
 
     // docker process contains `moby` in its process name.
     let parent_pid_cmdline =
@@ -285,201 +907,235 @@ fn valid_pid_is_container(pid: u32) -> Result<bool, Box<dyn std::error::Error>>
     Ok(true)
 }
 
-fn encode_request_path(path: &str, params: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let mut encoded_params = form_urlencoded::Serializer::new(String::new());
-    // valid parameter contains '=' character.
-    if !params.contains('=') {
-        // eprintln!("Error happend");
-        Err(Error::InvalidParameter)?
-    }
+pub(crate) fn parent_pid(pid: u32) -> Result<u32, Box<dyn std::error::Error>> {
+    // `/proc/{pid}/cmdline` contains many arguments.
+    let pid_string = std::fs::read_to_string(&format!("/proc/{pid}/stat", pid = pid))?;
+    // the parent process id is the forth argument.
+    let parent_pid = pid_string
+        .split_whitespace()
+        .nth(3)
+        .ok_or(Error::InvalidPid)?
+        .parse::<u32>()?;
 
-    params.split('&').for_each(|param| {
-        let k_v = param.split_once('=').unwrap();
-        encoded_params.append_pair(k_v.0, k_v.1);
-    });
-    let encoded_params = encoded_params.finish();
-    let request_path = format!(
-        "{path}?{encoded_params}",
-        path = path,
-        encoded_params = encoded_params
-    );
+    Ok(parent_pid)
+}
 
-    Ok(request_path)
+pub(crate) fn start_time(pid: u32) -> Result<u64, Box<dyn std::error::Error>> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat", pid = pid))?;
+    Ok(parse_start_time(&stat)?)
 }
 
-/// Hitting Docker API Interface.  
-/// See API reference: https://docs.docker.com/engine/api/latest/
-///
-/// # Params:
-///
-/// - method: GET, POST, etc
-/// - path: "/containers/json", etc
-/// - parameter: Some("filters={"name":"hoge"}"), None, etc
-///
-/// # response:
-///
-/// type: String  
-/// receiving json format:
-///
-/// ```ignore
-/// {"containers": {API response body}}
-/// ```
-///
-/// So, We must receive values using `containers` key.  
-/// Deserializing by `serde_json::from_str()` is useful.
-///
-/// # Example:
-///
-/// Receiveing Array Value
-///
-/// ```ignore
-/// struct ListResponse {
-///     // Vector.
-///     containers: Vec<ListValues>
-/// }
-///
-/// ... snip ...
-///
-/// let docker_info: ListResponse = serde_json::from_str(&request_docker_api("GET", "/containers/json", Some(&format!(r#"all=true&filters={{"name": ["{}"]}}"#, name)))?)?;
-/// ```
-///
-/// Receiveing Object Value
-///
-/// ```ignore
-/// struct InspectResponse {
-///     // Not a Vector.
-///     containers: InspectValues
-/// }
-///
-/// ... snip ...
-///
-/// let docker_info: InspectResponse = serde_json::from_str(&request_docker_api("GET", &format!("/containers/{id}/json", id = id), None)?)?;
-/// ```
-fn request_docker_api(
-    method: &str,
-    path: &str,
-    parameter: Option<&str>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let docker_sock = std::path::Path::new("/var/run/docker.sock");
-
-    // Generate request body
-    let mut request_path = String::new();
-    if let Some(parameter) = parameter {
-        request_path = encode_request_path(path, parameter)?;
-    } else {
-        request_path = path.to_string();
-    }
-    let request = format!(
-        "{method} {request_path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
-        method = method,
-        request_path = request_path
-    );
+/// `starttime` is the 22nd field of `/proc/{pid}/stat`.
+/// The command name in the 2nd field may contain spaces, so count from its closing `)`.
+fn parse_start_time(stat: &str) -> Result<u64, Error> {
+    let (_, after_comm) = stat.rsplit_once(')').ok_or(Error::InvalidPid)?;
 
-    // println!("request: `{:?}`", request);
-    // Request to docker api
-    let mut stream = UnixStream::connect(docker_sock)?;
-    stream.write_all(request.as_bytes())?;
-    let mut response = String::new();
-    stream.read_to_string(&mut response)?;
-    drop(stream);
-
-    let header_and_body = response
-        .split_once("\r\n\r\n")
-        .ok_or(Error::InvalidResponse)?;
-    let header = header_and_body.0.trim().to_string();
-
-    // Exclude response header
-    let mut response_body = header_and_body.1.trim().to_string();
-
-    // Api error catch
-    // println!("respo: ```{:?}```", response);
-
-    // check response header
-    // if returned other than 200
-    // return Error
-    let mut headers = [EMPTY_HEADER; 100];
-    let mut res = Response::new(&mut headers[..]);
-    res.parse(header.as_ref())?;
-    match res.code {
-        Some(code) => {
-            match DockerdResponse::new(code) {
-                DockerdResponse::NoError => { /* do nothing */ }
-                _ => {
-                    // Example: {"message":"No such container"}
-                    if let Some(message) = catch_error_message(&response)? {
-                        Err(Error::ApiResponseError(message))?
-                    }
-                    Err(Error::InvalidResponse)?
-                }
-            }
+    after_comm
+        .split_whitespace()
+        .nth(19)
+        .and_then(|start_time| start_time.parse().ok())
+        .ok_or(Error::InvalidPid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(status: &str, running: bool, paused: bool) -> DockerContainerState {
+        DockerContainerState {
+            Status: status.to_string(),
+            Running: running,
+            Paused: paused,
+            Pid: 0,
         }
-        None => Err(Error::InvalidResponse)?,
-    };
+    }
 
-    // Case of inspect, response contains some string like:
-    // 1522\r\n{"Id":...}\n\r\n0
-    let newline_number = response_body.match_indices('\n').count();
-    if newline_number > 1 {
-        response_body = response_body.split("\n").collect::<Vec<&str>>()[1]
-            .trim()
-            .to_string();
+    #[test]
+    fn test_pid_from_state_stopped() {
+        let err = pid_from_state("abcd", &state("exited", false, false)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ContainerNotRunning(status)) if status == "exited"
+        ));
     }
 
-    // wrap with a brath
-    // println!("res: ```{}```", response_body);
-    let response_body = format!(
-        r#"{{"containers":{response_body}}}"#,
-        response_body = response_body
-    );
+    #[test]
+    fn test_pid_from_state_paused() {
+        let err = pid_from_state("abcd", &state("paused", true, true)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ContainerPaused)
+        ));
+    }
 
-    Ok(response_body)
-}
+    #[test]
+    #[ignore]
+    fn test_convert_injesh_name_to_docker_id() {
+        let name = "tes";
+        let id = Container::convert_injesh_name_to_docker_id(name).unwrap();
+        println!("id: {}", id);
+    }
 
-fn catch_error_message(response: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    if !response.contains(r#""message":"#) {
-        return Ok(None);
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "4242 (my (app) 1) S 1 4242 4242 0 -1 4194560 2291 0 0 0 3 1 0 0 20 0 1 0 866133 12853248 1234 18446744073709551615";
+        assert_eq!(parse_start_time(stat).unwrap(), 866133);
     }
 
-    #[derive(Debug, Deserialize)]
-    struct Message {
-        message: String,
+    #[test]
+    fn test_get_pid_from_cgroup() {
+        let cgroup = std::env::temp_dir().join(format!("injesh-cgroup-{}", std::process::id()));
+        std::fs::create_dir_all(cgroup.join("child")).unwrap();
+        std::fs::write(
+            cgroup.join("cgroup.procs"),
+            format!("{}\n", std::process::id()),
+        )
+        .unwrap();
+        // PID 1 is always older than the test process
+        std::fs::write(cgroup.join("child").join("cgroup.procs"), "1\n").unwrap();
+
+        let pid = get_pid_from_cgroup(&cgroup).unwrap();
+        std::fs::remove_dir_all(&cgroup).unwrap();
+
+        assert_eq!(pid, 1);
     }
-    let message: Message = serde_json::from_str(&response)?;
 
-    Ok(Some(message.message))
-}
+    #[test]
+    fn test_use_proc_root() {
+        let data = DockerGraphDriverData {
+            LowerDir: path::PathBuf::from("/l"),
+            UpperDir: path::PathBuf::from("/u"),
+            MergedDir: path::PathBuf::from("/m"),
+            WorkDir: path::PathBuf::from("/w"),
+        };
+        // above PID_MAX_LIMIT, so it never shares the mount namespace of the test process
+        let pid = 4194305;
+        let mut container = Container::from_graph_driver(Runtime::Docker, "abcd", pid, data);
+        container.use_proc_root();
 
-pub enum DockerdResponse {
-    // 204 or 200
-    NoError,
-    // 404
-    NoSuchContainer,
-    // 500
-    ServerError,
-    NotFound,
-}
+        assert_eq!(container.fs_view(), FsView::ProcRoot);
+        assert_eq!(
+            container.mergeddir(),
+            &path::PathBuf::from("/proc/4194305/root")
+        );
+        assert_eq!(container.upperdir(), None);
+    }
 
-impl DockerdResponse {
-    fn new(response_id: u16) -> DockerdResponse {
-        match response_id {
-            200 => DockerdResponse::NoError,
-            204 => DockerdResponse::NoError,
-            404 => DockerdResponse::NoSuchContainer,
-            500 => DockerdResponse::ServerError,
-            _ => DockerdResponse::NotFound,
+    #[test]
+    fn test_from_pid_in_host_mount_namespace() {
+        // the test process shares its own mount namespace
+        let container = Container::from_pid(Runtime::Pid, "self", std::process::id());
+
+        assert_eq!(container.fs_view(), FsView::RootfsOnly);
+        assert_eq!(container.target_root(), None);
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let root = std::env::temp_dir().join(format!("injesh-overlaps-{}", std::process::id()));
+        let injesh_home = root.join("home/user/.injesh");
+        let other = root.join("var/lib/docker");
+        std::fs::create_dir_all(&injesh_home).unwrap();
+        std::fs::create_dir_all(&other).unwrap();
+
+        assert!(overlaps(&root, &injesh_home));
+        assert!(overlaps(&injesh_home.join("containers"), &injesh_home));
+        assert!(!overlaps(&other, &injesh_home));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_graph_driver_without_overlay() {
+        let vfs: DockerGraphDriver = serde_json::from_str(r#"{"Name":"vfs","Data":null}"#).unwrap();
+        let zfs: DockerGraphDriver = serde_json::from_str(
+            r#"{"Name":"zfs","Data":{"Dataset":"tank/docker/abcd","Mountpoint":"/var/lib/docker/zfs/graph/abcd"}}"#,
+        )
+        .unwrap();
+
+        assert!(vfs.Data.is_none());
+        assert_eq!(zfs.Data.unwrap().MergedDir, path::PathBuf::new());
+    }
+
+    fn list(id: &str, name: &str) -> DockerContainerList {
+        DockerContainerList {
+            Id: format!("{:0<64}", id),
+            Names: vec![format!("/{}", name)],
+            Labels: HashMap::new(),
+            Image: String::new(),
+            Status: String::new(),
+            Ports: Vec::new(),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_select_container_id_exact_name() {
+        let containers = vec![list("aaa1", "web-worker"), list("bbb2", "web")];
+
+        let id = select_container_id(&containers, "web").unwrap();
+        assert_eq!(id, format!("{:0<64}", "bbb2"));
+    }
 
     #[test]
-    #[ignore]
-    fn test_convert_injesh_name_to_docker_id() {
-        let name = "tes";
-        let id = Container::convert_injesh_name_to_docker_id(name).unwrap();
-        println!("id: {}", id);
+    fn test_select_container_id_ambiguous_prefix() {
+        let containers = vec![
+            list("aaa1", "web"),
+            list("aaa2", "db"),
+            list("bbb3", "cache"),
+        ];
+
+        assert_eq!(
+            select_container_id(&containers, "bbb").unwrap(),
+            format!("{:0<64}", "bbb3")
+        );
+        assert!(matches!(
+            select_container_id(&containers, "aaa"),
+            Err(Error::AmbiguousContainer(candidates)) if candidates.len() == 2
+        ));
+        assert!(matches!(
+            select_container_id(&containers, "web-worker"),
+            Err(Error::ContainerNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_select_container_id_ambiguous_short_ids() {
+        let mut containers = vec![list("", "web"), list("", "db")];
+        containers[0].Id = "ab1".to_string();
+        containers[1].Id = "ab2".to_string();
+
+        assert!(matches!(
+            select_container_id(&containers, "ab"),
+            Err(Error::AmbiguousContainer(candidates))
+                if candidates == ["ab1 (web)", "ab2 (db)"]
+        ));
+    }
+
+    #[test]
+    fn test_select_replica() {
+        let replicas = || {
+            (1..=10)
+                .map(|number| {
+                    let mut container = list(&number.to_string(), &format!("shop-api-{}", number));
+                    container
+                        .Labels
+                        .insert(COMPOSE_NUMBER_LABEL.to_string(), number.to_string());
+                    container
+                })
+                .rev()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            select_replica(replicas(), Some(2)).unwrap(),
+            format!("{:0<64}", "2")
+        );
+        assert!(matches!(
+            select_replica(replicas(), None),
+            Err(Error::SeveralReplicas(candidates)) if candidates[9] == "10: shop-api-10"
+        ));
+        assert!(matches!(
+            select_replica(replicas(), Some(11)),
+            Err(Error::ReplicaNotFound(11))
+        ));
     }
 }
@@ -0,0 +1,337 @@
+//! containerdのコンテナをデバック対象にする
+//!
+//! containerdのgRPC APIで、指定したnamespace(`default`、Kubernetesのノードでは`k8s.io`など)から探す
+
+use crate::container::{self, Container, DockerGraphDriverData, Runtime};
+use crate::grpc;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+const CONTAINERD_SOCK: &str = "/run/containerd/containerd.sock";
+const TASK_STATE_DIR: &str = "/run/containerd/io.containerd.runtime.v2.task";
+/// 人が読めるコンテナ名を持つラベル
+const NAME_LABELS: [&str; 2] = ["io.kubernetes.container.name", "nerdctl/name"];
+
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+#[derive(Debug)]
+pub enum Error {
+    AmbiguousName(Vec<String>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AmbiguousName(candidates) => write!(
+                f,
+                "containerd: name matches several running containers: {}",
+                candidates.join(", ")
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// `containerd.services.containers.v1.Container`
+#[derive(Debug, Default)]
+struct ContainerdContainer {
+    id: String,
+    labels: HashMap<String, String>,
+    snapshotter: String,
+    snapshot_key: String,
+}
+
+impl ContainerdContainer {
+    fn decode(message: &[u8]) -> Result<ContainerdContainer, grpc::Error> {
+        let mut container = ContainerdContainer::default();
+        for (field, value) in grpc::decode(message)? {
+            match field {
+                1 => container.id = value.as_str()?.to_string(),
+                2 => {
+                    let (key, label) = grpc::decode_map_entry(value.as_bytes()?)?;
+                    container.labels.insert(key, label);
+                }
+                6 => container.snapshotter = value.as_str()?.to_string(),
+                7 => container.snapshot_key = value.as_str()?.to_string(),
+                _ => {}
+            }
+        }
+
+        Ok(container)
+    }
+
+    fn matches_prefix(&self, prefix: &str) -> bool {
+        self.id.starts_with(prefix)
+            || NAME_LABELS.iter().any(|label| {
+                self.labels
+                    .get(*label)
+                    .is_some_and(|name| name.starts_with(prefix))
+            })
+    }
+}
+
+/// `containerd.v1.types.Process`
+#[derive(Debug, Default)]
+struct Task {
+    container_id: String,
+    pid: u32,
+    status: u64,
+}
+
+impl Task {
+    fn decode(message: &[u8]) -> Result<Task, grpc::Error> {
+        let mut task = Task::default();
+        for (field, value) in grpc::decode(message)? {
+            match field {
+                1 => task.container_id = value.as_str()?.to_string(),
+                3 => task.pid = value.as_u64()? as u32,
+                4 => task.status = value.as_u64()?,
+                _ => {}
+            }
+        }
+
+        Ok(task)
+    }
+
+    fn is_running(&self) -> bool {
+        self.status == 2
+    }
+
+    /// `containerd.v1.types.Status`を、Dockerの場合と同じエラーにする
+    fn check_running(&self) -> Result<(), container::Error> {
+        match self.status {
+            2 => Ok(()),
+            4 | 5 => Err(container::Error::ContainerPaused),
+            1 => Err(container::Error::ContainerNotRunning("created".to_string())),
+            3 => Err(container::Error::ContainerNotRunning("stopped".to_string())),
+            _ => Err(container::Error::ContainerNotRunning("unknown".to_string())),
+        }
+    }
+}
+
+/// ID、IDの前方一致、または名前のラベルの前方一致でcontainerdのコンテナを解決する
+pub fn new(namespace: &str, id_or_prefix: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    let socket = Path::new(CONTAINERD_SOCK);
+    let tasks = list_tasks(socket, namespace)?;
+    let target = select_container(list_containers(socket, namespace)?, &tasks, id_or_prefix)?;
+
+    let task = tasks
+        .iter()
+        .find(|task| task.container_id == target.id)
+        .ok_or_else(|| container::Error::ContainerNotRunning("no task".to_string()))?;
+    task.check_running()?;
+    if !container::pid_belongs_to_container(task.pid, &target.id) {
+        Err(container::Error::ContainerProcessNotFound)?
+    }
+
+    // btrfs、zfs、devmapperなど他のsnapshotterには重ねるレイヤーが無い
+    let runtime = Runtime::Containerd(namespace.to_string());
+    let mut graph_driver_data = match snapshot_dirs(socket, namespace, &target)? {
+        Some(graph_driver_data) => graph_driver_data,
+        None => return Ok(Container::from_pid(runtime, &target.id, task.pid)),
+    };
+    graph_driver_data.MergedDir = Path::new(TASK_STATE_DIR)
+        .join(namespace)
+        .join(&target.id)
+        .join("rootfs");
+
+    Ok(Container::from_graph_driver(
+        runtime,
+        &target.id,
+        task.pid,
+        graph_driver_data,
+    ))
+}
+
+/// IDが完全に一致するものを優先する。それ以外は前方一致するコンテナ、
+/// またはそのうちタスクが実行中のものがちょうど1つである必要がある
+fn select_container(
+    containers: Vec<ContainerdContainer>,
+    tasks: &[Task],
+    id_or_prefix: &str,
+) -> Result<ContainerdContainer, Box<dyn std::error::Error>> {
+    let mut candidates: Vec<ContainerdContainer> = containers
+        .into_iter()
+        .filter(|container| container.matches_prefix(id_or_prefix))
+        .collect();
+
+    if let Some(index) = candidates.iter().position(|c| c.id == id_or_prefix) {
+        return Ok(candidates.swap_remove(index));
+    }
+    if candidates.len() > 1 {
+        candidates.retain(|c| {
+            tasks
+                .iter()
+                .any(|task| task.container_id == c.id && task.is_running())
+        });
+    }
+
+    match candidates.len() {
+        0 => Err(container::Error::ContainerNotFound)?,
+        1 => Ok(candidates.remove(0)),
+        _ => Err(Error::AmbiguousName(
+            candidates.into_iter().map(|c| c.id).collect(),
+        ))?,
+    }
+}
+
+fn list_containers(
+    socket: &Path,
+    namespace: &str,
+) -> Result<Vec<ContainerdContainer>, Box<dyn std::error::Error>> {
+    let response = grpc::unary(
+        socket,
+        "/containerd.services.containers.v1.Containers/List",
+        &[("containerd-namespace", namespace)],
+        Vec::new(),
+    )?;
+
+    let mut containers = Vec::new();
+    for (field, value) in grpc::decode(&response)? {
+        if field == 1 {
+            containers.push(ContainerdContainer::decode(value.as_bytes()?)?);
+        }
+    }
+
+    Ok(containers)
+}
+
+fn list_tasks(socket: &Path, namespace: &str) -> Result<Vec<Task>, Box<dyn std::error::Error>> {
+    let response = grpc::unary(
+        socket,
+        "/containerd.services.tasks.v1.Tasks/List",
+        &[("containerd-namespace", namespace)],
+        Vec::new(),
+    )?;
+
+    let mut tasks = Vec::new();
+    for (field, value) in grpc::decode(&response)? {
+        if field == 1 {
+            tasks.push(Task::decode(value.as_bytes()?)?);
+        }
+    }
+
+    Ok(tasks)
+}
+
+/// コンテナのactiveなsnapshotのoverlayのレイヤーを探す
+///
+/// snapshotがoverlayでマウントされない場合は`None`
+fn snapshot_dirs(
+    socket: &Path,
+    namespace: &str,
+    target: &ContainerdContainer,
+) -> Result<Option<DockerGraphDriverData>, Box<dyn std::error::Error>> {
+    let request = grpc::Encoder::new()
+        .string(1, &target.snapshotter)
+        .string(2, &target.snapshot_key)
+        .finish();
+    let response = grpc::unary(
+        socket,
+        "/containerd.services.snapshots.v1.Snapshots/Mounts",
+        &[("containerd-namespace", namespace)],
+        request,
+    )?;
+
+    // `containerd.types.Mount`: type = 1, source = 2, options = 4
+    for (field, value) in grpc::decode(&response)? {
+        if field != 1 {
+            continue;
+        }
+        let mut mount_type = String::new();
+        let mut options = Vec::new();
+        for (mount_field, mount_value) in grpc::decode(value.as_bytes()?)? {
+            match mount_field {
+                1 => mount_type = mount_value.as_str()?.to_string(),
+                4 => options.push(mount_value.as_str()?.to_string()),
+                _ => {}
+            }
+        }
+        if mount_type != "overlay" {
+            return Ok(None);
+        }
+        return Ok(Some(overlay_dirs(&options)));
+    }
+
+    Err(container::Error::GraphDriverPathNotFound)?
+}
+
+/// overlayのマウントオプションから`lowerdir`、`upperdir`、`workdir`を取り出す
+fn overlay_dirs(options: &[String]) -> DockerGraphDriverData {
+    let option = |name: &str| {
+        options
+            .iter()
+            .find_map(|option| option.strip_prefix(&format!("{}=", name)))
+            .map(PathBuf::from)
+            .unwrap_or_default()
+    };
+
+    DockerGraphDriverData {
+        LowerDir: option("lowerdir"),
+        UpperDir: option("upperdir"),
+        MergedDir: PathBuf::new(),
+        WorkDir: option("workdir"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(id: &str, name: &str) -> ContainerdContainer {
+        let mut labels = HashMap::new();
+        labels.insert(NAME_LABELS[0].to_string(), name.to_string());
+        ContainerdContainer {
+            id: id.to_string(),
+            labels,
+            ..Default::default()
+        }
+    }
+
+    fn task(container_id: &str, status: u64) -> Task {
+        Task {
+            container_id: container_id.to_string(),
+            pid: 1,
+            status,
+        }
+    }
+
+    #[test]
+    fn test_select_container_by_name_prefix() {
+        // 再起動したPodは停止したコンテナを残す
+        let containers = vec![container("aaa1", "nginx"), container("bbb2", "nginx")];
+        let tasks = vec![task("aaa1", 3), task("bbb2", 2)];
+
+        let selected = select_container(containers, &tasks, "ngi").unwrap();
+        assert_eq!(selected.id, "bbb2");
+    }
+
+    #[test]
+    fn test_select_container_ambiguous() {
+        let containers = vec![container("aaa1", "nginx"), container("aaa2", "redis")];
+        let tasks = vec![task("aaa1", 2), task("aaa2", 2)];
+
+        let err = select_container(containers, &tasks, "aaa").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::AmbiguousName(candidates)) if candidates.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_overlay_dirs() {
+        let options = vec![
+            "index=off".to_string(),
+            "workdir=/s/2/work".to_string(),
+            "upperdir=/s/2/fs".to_string(),
+            "lowerdir=/s/1/fs".to_string(),
+        ];
+        let dirs = overlay_dirs(&options);
+
+        assert_eq!(dirs.LowerDir, PathBuf::from("/s/1/fs"));
+        assert_eq!(dirs.UpperDir, PathBuf::from("/s/2/fs"));
+        assert_eq!(dirs.WorkDir, PathBuf::from("/s/2/work"));
+    }
+}
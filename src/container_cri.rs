@@ -0,0 +1,361 @@
+//! KubernetesのPodのコンテナをデバック対象にする
+//!
+//! ノードのランタイム(containerd、CRI-O、cri-dockerd)のCRI `RuntimeService`で探す
+
+use crate::container::{self, Container, Runtime};
+use crate::grpc;
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+const CRI_SOCKS: [&str; 3] = [
+    "/run/containerd/containerd.sock",
+    "/var/run/crio/crio.sock",
+    "/var/run/cri-dockerd.sock",
+];
+const RUNTIME_SERVICE: &str = "/runtime.v1.RuntimeService";
+const POD_NAMESPACE_LABEL: &str = "io.kubernetes.pod.namespace";
+const POD_NAME_LABEL: &str = "io.kubernetes.pod.name";
+/// `runtime.v1.ContainerState`
+const CONTAINER_RUNNING: u64 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    SocketNotFound,
+    PodNotFound(String),
+    ContainerNotFound(String),
+    ContainerNameRequired(Vec<String>),
+    PidNotFound,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SocketNotFound => write!(f, "cri: runtime socket not found"),
+            Error::PodNotFound(pod) => write!(f, "cri: pod {} not found", pod),
+            Error::ContainerNotFound(name) => {
+                write!(f, "cri: no running container {} in the pod", name)
+            }
+            Error::ContainerNameRequired(names) => write!(
+                f,
+                "cri: pod has several containers, choose one with --container: {}",
+                names.join(", ")
+            ),
+            Error::PidNotFound => write!(f, "cri: runtime did not report the container PID"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// `ListContainers`の`runtime.v1.Container`
+#[derive(Debug, Default)]
+struct CriContainer {
+    id: String,
+    name: String,
+}
+
+/// Pod `<namespace>/<pod>`(`default`の場合は`<pod>`のみ)のコンテナを解決する
+///
+/// コンテナが1つのPodでは`container_name`を省略できる
+pub fn new(
+    pod: &str,
+    container_name: Option<&str>,
+) -> Result<Container, Box<dyn std::error::Error>> {
+    let socket = socket()?;
+    let (namespace, pod_name) = pod.split_once('/').unwrap_or(("default", pod));
+
+    let (container_id, pid) = resolve(&socket, namespace, pod_name, container_name)?;
+    verified(&container_id, pid)
+}
+
+/// `exec`のために、CRIのコンテナIDでコンテナを解決し直す
+pub fn from_id(container_id: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    let socket = socket()?;
+
+    let pid = container_pid(&socket, container_id)?;
+    verified(container_id, pid)
+}
+
+fn verified(container_id: &str, pid: u32) -> Result<Container, Box<dyn std::error::Error>> {
+    if !container::pid_belongs_to_container(pid, container_id) {
+        Err(container::Error::ContainerProcessNotFound)?
+    }
+
+    Ok(Container::from_pid(Runtime::Cri, container_id, pid))
+}
+
+fn socket() -> Result<PathBuf, Error> {
+    CRI_SOCKS
+        .iter()
+        .map(PathBuf::from)
+        .find(|socket| socket.exists())
+        .ok_or(Error::SocketNotFound)
+}
+
+/// 実行中のコンテナのIDとPIDを返す
+fn resolve(
+    socket: &Path,
+    namespace: &str,
+    pod_name: &str,
+    container_name: Option<&str>,
+) -> Result<(String, u32), Box<dyn std::error::Error>> {
+    let sandbox_id = pod_sandbox_id(socket, namespace, pod_name)?;
+    let mut containers = running_containers(socket, &sandbox_id)?;
+
+    let target = match container_name {
+        Some(name) => {
+            let index = containers
+                .iter()
+                .position(|container| container.name == name)
+                .ok_or_else(|| Error::ContainerNotFound(name.to_string()))?;
+            containers.swap_remove(index)
+        }
+        None if containers.len() == 1 => containers.remove(0),
+        None => Err(Error::ContainerNameRequired(
+            containers.into_iter().map(|c| c.name).collect(),
+        ))?,
+    };
+
+    let pid = container_pid(socket, &target.id)?;
+    Ok((target.id, pid))
+}
+
+/// kubeletが付けるラベルで探した、Podの準備完了したsandboxのID
+fn pod_sandbox_id(
+    socket: &Path,
+    namespace: &str,
+    pod_name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // PodSandboxFilter: state = 2 (空の値はSANDBOX_READY), label_selector = 3
+    let filter = grpc::Encoder::new()
+        .bytes(2, &[])
+        .map_entry(3, POD_NAMESPACE_LABEL, namespace)
+        .map_entry(3, POD_NAME_LABEL, pod_name)
+        .finish();
+    let request = grpc::Encoder::new().bytes(1, &filter).finish();
+    let response = grpc::unary(
+        socket,
+        &format!("{}/ListPodSandbox", RUNTIME_SERVICE),
+        &[],
+        request,
+    )?;
+
+    // PodSandbox: id = 1
+    for (field, sandbox) in grpc::decode(&response)? {
+        if field != 1 {
+            continue;
+        }
+        for (sandbox_field, value) in grpc::decode(sandbox.as_bytes()?)? {
+            if sandbox_field == 1 {
+                return Ok(value.as_str()?.to_string());
+            }
+        }
+    }
+
+    Err(Error::PodNotFound(format!("{}/{}", namespace, pod_name)))?
+}
+
+fn running_containers(
+    socket: &Path,
+    sandbox_id: &str,
+) -> Result<Vec<CriContainer>, Box<dyn std::error::Error>> {
+    // ContainerFilter: state = 2, pod_sandbox_id = 3
+    let state = grpc::Encoder::new().uint(1, CONTAINER_RUNNING).finish();
+    let filter = grpc::Encoder::new()
+        .bytes(2, &state)
+        .string(3, sandbox_id)
+        .finish();
+    let request = grpc::Encoder::new().bytes(1, &filter).finish();
+    let response = grpc::unary(
+        socket,
+        &format!("{}/ListContainers", RUNTIME_SERVICE),
+        &[],
+        request,
+    )?;
+
+    // Container: id = 1, metadata = 3 (ContainerMetadata: name = 1)
+    let mut containers = Vec::new();
+    for (field, value) in grpc::decode(&response)? {
+        if field != 1 {
+            continue;
+        }
+        let mut container = CriContainer::default();
+        for (container_field, value) in grpc::decode(value.as_bytes()?)? {
+            match container_field {
+                1 => container.id = value.as_str()?.to_string(),
+                3 => {
+                    for (metadata_field, value) in grpc::decode(value.as_bytes()?)? {
+                        if metadata_field == 1 {
+                            container.name = value.as_str()?.to_string();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        containers.push(container);
+    }
+
+    Ok(containers)
+}
+
+/// PIDはランタイム固有の詳細な`info`にのみ含まれる。既知のランタイムではいずれも`pid`キーを持つJSON
+fn container_pid(socket: &Path, container_id: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    // ContainerStatusRequest: container_id = 1, verbose = 2
+    let request = grpc::Encoder::new()
+        .string(1, container_id)
+        .bool(2, true)
+        .finish();
+    let response = grpc::unary(
+        socket,
+        &format!("{}/ContainerStatus", RUNTIME_SERVICE),
+        &[],
+        request,
+    )?;
+
+    // ContainerStatusResponse: status = 1 (ContainerStatus: state = 3), info = 2
+    let mut state = None;
+    let mut pid = None;
+    for (field, value) in grpc::decode(&response)? {
+        match field {
+            1 => {
+                for (status_field, value) in grpc::decode(value.as_bytes()?)? {
+                    if status_field == 3 {
+                        state = Some(value.as_u64()?);
+                    }
+                }
+            }
+            2 => {
+                let (key, info) = grpc::decode_map_entry(value.as_bytes()?)?;
+                if key == "info" {
+                    let info: serde_json::Value = serde_json::from_str(&info)?;
+                    pid = info["pid"].as_u64();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // proto3ではデフォルト値のCONTAINER_CREATEDは省略される
+    if state.unwrap_or(0) != CONTAINER_RUNNING {
+        Err(container::Error::ContainerNotRunning(
+            "not running".to_string(),
+        ))?
+    }
+    match pid {
+        Some(pid) if pid != 0 => Ok(pid as u32),
+        _ => Err(Error::PidNotFound)?,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use bytes::Bytes;
+    use std::thread;
+
+    /// Pod `web/frontend`とそのコンテナ`app`、`proxy`のみを持つCRIランタイム
+    fn runtime_service(method: &str, request: &[u8]) -> Vec<u8> {
+        match method {
+            "/runtime.v1.RuntimeService/ListPodSandbox" => {
+                let sandbox = grpc::Encoder::new().string(1, "sandbox1").finish();
+                grpc::Encoder::new().bytes(1, &sandbox).finish()
+            }
+            "/runtime.v1.RuntimeService/ListContainers" => {
+                let mut response = grpc::Encoder::new();
+                for (id, name) in [("c1", "app"), ("c2", "proxy")] {
+                    let metadata = grpc::Encoder::new().string(1, name).finish();
+                    let container = grpc::Encoder::new()
+                        .string(1, id)
+                        .string(2, "sandbox1")
+                        .bytes(3, &metadata)
+                        .finish();
+                    response.bytes(1, &container);
+                }
+                response.finish()
+            }
+            "/runtime.v1.RuntimeService/ContainerStatus" => {
+                let fields = grpc::decode(request).unwrap();
+                let id = fields[0].1.as_str().unwrap();
+                let pid = if id == "c1" { 4242 } else { 4343 };
+                let status = grpc::Encoder::new()
+                    .string(1, id)
+                    .uint(3, CONTAINER_RUNNING)
+                    .finish();
+                grpc::Encoder::new()
+                    .bytes(1, &status)
+                    .map_entry(2, "info", &format!(r#"{{"pid":{}}}"#, pid))
+                    .finish()
+            }
+            _ => panic!("unexpected method {}", method),
+        }
+    }
+
+    /// Unixソケットで`connections`個のgRPCの接続に応答する
+    fn serve(name: &str, connections: usize) -> (PathBuf, thread::JoinHandle<()>) {
+        let (socket, listener) = test_server::bind(&format!("cri-{}", name));
+        listener.set_nonblocking(true).unwrap();
+
+        let handle = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+                for _ in 0..connections {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let mut connection = h2::server::handshake(stream).await.unwrap();
+                    while let Some(request) = connection.accept().await {
+                        let (request, mut respond) = request.unwrap();
+                        let method = request.uri().path().to_string();
+                        let mut body = request.into_body();
+                        let mut data = Vec::new();
+                        while let Some(chunk) = body.data().await {
+                            data.extend_from_slice(&chunk.unwrap());
+                        }
+
+                        let reply = runtime_service(&method, &grpc::unframe(&data).unwrap());
+                        let response = http::Response::builder()
+                            .header("content-type", "application/grpc")
+                            .body(())
+                            .unwrap();
+                        let mut send = respond.send_response(response, false).unwrap();
+                        send.send_data(Bytes::from(grpc::frame(&reply)), false)
+                            .unwrap();
+                        let mut trailers = http::HeaderMap::new();
+                        trailers.insert("grpc-status", "0".parse().unwrap());
+                        send.send_trailers(trailers).unwrap();
+                    }
+                }
+            });
+        });
+
+        (socket, handle)
+    }
+
+    #[test]
+    fn test_resolve_by_container_name() {
+        let (socket, handle) = serve("name", 3);
+
+        let (id, pid) = resolve(&socket, "web", "frontend", Some("proxy")).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(id, "c2");
+        assert_eq!(pid, 4343);
+    }
+
+    #[test]
+    fn test_resolve_requires_container_name() {
+        let (socket, handle) = serve("required", 2);
+
+        let err = resolve(&socket, "web", "frontend", None).unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::ContainerNameRequired(names)) if names == &["app", "proxy"]
+        ));
+    }
+}
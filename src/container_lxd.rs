@@ -0,0 +1,202 @@
+//! LXDのシステムコンテナをデバック対象にする
+//!
+//! LXDのREST APIで解決する。snapとディストリビューションのパッケージでソケットの場所が異なるため、順に試す
+
+use crate::container::{self, Container, Runtime};
+use crate::docker_client::DockerClient;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::{env, error, fmt};
+
+const SNAP_LXD_SOCK: &str = "/var/snap/lxd/common/lxd/unix.socket";
+const LXD_SOCK: &str = "/var/lib/lxd/unix.socket";
+const LXD_API_PREFIX: &str = "/1.0/instances";
+
+#[derive(Debug)]
+pub enum Error {
+    SocketNotFound,
+    NotAContainer(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SocketNotFound => write!(f, "lxd: API socket not found"),
+            Error::NotAContainer(instance_type) => {
+                write!(f, "lxd: instance is a {}, not a container", instance_type)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// LXD APIの同期レスポンス
+#[derive(Debug, Deserialize)]
+struct LxdResponse<T> {
+    metadata: T,
+}
+
+/// `GET /1.0/instances/<name>`
+#[derive(Debug, Deserialize)]
+struct LxdInstance {
+    #[serde(rename = "type")]
+    instance_type: String,
+}
+
+/// `GET /1.0/instances/<name>/state`
+#[derive(Debug, Deserialize)]
+struct LxdInstanceState {
+    status: String,
+    pid: u32,
+}
+
+/// 名前でLXDのコンテナを解決する
+///
+/// コンテナを知っている最初のAPIソケットの結果を使う
+pub fn new(name: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    let mut last_error: Box<dyn std::error::Error> = Box::new(Error::SocketNotFound);
+
+    for socket in socket_candidates() {
+        if !socket.exists() {
+            continue;
+        }
+        match resolve(&socket, name) {
+            Ok(container) => return Ok(container),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// 試すAPIソケット。`lxc`コマンドと同様に`$LXD_DIR`が先
+fn socket_candidates() -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Ok(lxd_dir) = env::var("LXD_DIR") {
+        candidates.push(Path::new(&lxd_dir).join("unix.socket"));
+    }
+    candidates.push(PathBuf::from(SNAP_LXD_SOCK));
+    candidates.push(PathBuf::from(LXD_SOCK));
+
+    candidates
+}
+
+/// ルートファイルシステムは`/proc/<initのPID>/root`を使う
+///
+/// snapのストレージプールは独自のmount namespaceにマウントされており、LXDが返すパスにはホストから到達できない
+fn resolve(socket: &Path, name: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    // 存在しないインスタンスのエラーにはDockerのような`message`が無いため、先に一覧から探す
+    let mut client = DockerClient::unix(socket);
+    let instances: LxdResponse<Vec<String>> = client.get(LXD_API_PREFIX, &[])?;
+    let instance_path = format!("{}/{}", LXD_API_PREFIX, name);
+    if !instances.metadata.contains(&instance_path) {
+        Err(container::Error::ContainerNotFound)?
+    }
+
+    let instance: LxdResponse<LxdInstance> = client.get(&instance_path, &[])?;
+    let instance_type = instance.metadata.instance_type;
+    if instance_type != "container" {
+        Err(Error::NotAContainer(instance_type))?
+    }
+
+    let state: LxdResponse<LxdInstanceState> =
+        client.get(&format!("{}/state", instance_path), &[])?;
+    let state = state.metadata;
+    match state.status.as_str() {
+        "Running" => {}
+        "Frozen" => Err(container::Error::ContainerPaused)?,
+        status => Err(container::Error::ContainerNotRunning(status.to_lowercase()))?,
+    }
+
+    let cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", state.pid)).unwrap_or_default();
+    if !in_instance_cgroup(&cgroup, name) {
+        Err(container::Error::ContainerProcessNotFound)?
+    }
+
+    Ok(Container::from_pid(Runtime::Lxd, name, state.pid))
+}
+
+/// `/proc/<pid>/cgroup`の内容`cgroup`が、インスタンス`name`のcgroupを含むかどうか
+///
+/// cgroupは`lxc.payload.<name>`、古いリリースでは`lxc/<name>`。
+/// `web`と`web2`を区別するため、パスの要素を完全に一致させる
+fn in_instance_cgroup(cgroup: &str, name: &str) -> bool {
+    let payload = format!("lxc.payload.{}", name);
+
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .any(|path| {
+            let components: Vec<&str> = path.split('/').collect();
+            components.iter().any(|component| *component == payload)
+                || components.windows(2).any(|pair| pair == ["lxc", name])
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, json};
+
+    fn sync(metadata: &str) -> String {
+        json(
+            200,
+            &format!(
+                r#"{{"type":"sync","status":"Success","status_code":200,"metadata":{}}}"#,
+                metadata
+            ),
+        )
+    }
+
+    #[test]
+    fn test_resolve_frozen() {
+        let (socket, handle) = test_server::serve(
+            "lxd-frozen",
+            vec![
+                sync(r#"["/1.0/instances/web"]"#),
+                sync(r#"{"name":"web","type":"container"}"#),
+                sync(r#"{"status":"Frozen","pid":1234}"#),
+            ],
+        );
+
+        let err = resolve(&socket, "web").unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<container::Error>(),
+            Some(container::Error::ContainerPaused)
+        ));
+    }
+
+    #[test]
+    fn test_in_instance_cgroup_overlapping_names() {
+        let payload = "0::/lxc.payload.web2/init.scope\n";
+        let legacy = "4:memory:/lxc/web2\n1:name=systemd:/lxc/web2/init.scope\n";
+
+        assert!(in_instance_cgroup(payload, "web2"));
+        assert!(!in_instance_cgroup(payload, "web"));
+        assert!(in_instance_cgroup(legacy, "web2"));
+        assert!(!in_instance_cgroup(legacy, "web"));
+        assert!(!in_instance_cgroup("0::/lxc.monitor.web\n", "web"));
+    }
+
+    #[test]
+    fn test_resolve_virtual_machine() {
+        let (socket, handle) = test_server::serve(
+            "lxd-vm",
+            vec![
+                sync(r#"["/1.0/instances/vm"]"#),
+                sync(r#"{"name":"vm","type":"virtual-machine"}"#),
+            ],
+        );
+
+        let err = resolve(&socket, "vm").unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::NotAContainer(instance_type)) if instance_type == "virtual-machine"
+        ));
+    }
+}
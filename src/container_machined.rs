@@ -0,0 +1,102 @@
+//! systemd-machinedに登録されたマシンをデバック対象にする
+//!
+//! `/run/systemd/machines/<name>`の状態ファイルを読み込むため、D-Busには接続しない
+
+use crate::container::{self, Container, Runtime};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+const MACHINES_STATE_DIR: &str = "/run/systemd/machines";
+
+#[derive(Debug)]
+pub enum Error {
+    MachineNotFound(String),
+    NotAContainer(String),
+    LeaderNotFound,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MachineNotFound(name) => write!(f, "machined: machine {} not found", name),
+            Error::NotAContainer(class) => {
+                write!(f, "machined: machine is a {}, not a container", class)
+            }
+            Error::LeaderNotFound => write!(f, "machined: leader process not found"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// 名前でマシンを解決する
+///
+/// ホスト上にルートディレクトリ`ROOT`を持つマシンはそれを使い、無い場合はリーダープロセスのルートを使う
+pub fn new(name: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    if name.is_empty() || name.contains('/') {
+        Err(Error::MachineNotFound(name.to_string()))?
+    }
+    let state = std::fs::read_to_string(Path::new(MACHINES_STATE_DIR).join(name))
+        .map_err(|_| Error::MachineNotFound(name.to_string()))?;
+    let state = parse_state(&state);
+
+    match state.get("CLASS").map(String::as_str) {
+        Some("container") | None => {}
+        Some(class) => Err(Error::NotAContainer(class.to_string()))?,
+    }
+
+    let pid: u32 = state
+        .get("LEADER")
+        .and_then(|leader| leader.parse().ok())
+        .ok_or(Error::LeaderNotFound)?;
+    if !Path::new(&format!("/proc/{}", pid)).exists() {
+        Err(Error::LeaderNotFound)?
+    }
+    // リーダープロセスはマシンのscopeユニットに属したままのはず
+    if let Some(scope) = state.get("SCOPE") {
+        if !container::pid_belongs_to_container(pid, scope) {
+            Err(Error::LeaderNotFound)?
+        }
+    }
+
+    let root = match state.get("ROOT") {
+        Some(root) if Path::new(root).is_dir() => PathBuf::from(root),
+        _ => PathBuf::from(format!("/proc/{}/root", pid)),
+    };
+
+    Ok(Container::from_root(Runtime::Machined, name, pid, root))
+}
+
+/// マシンの状態ファイルの`KEY=VALUE`の行を読み込む
+fn parse_state(state: &str) -> HashMap<String, String> {
+    state
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_state() {
+        let state = parse_state(
+            "# This is private data. Do not parse.\n\
+             NAME=debian\n\
+             SCOPE=machine-debian.scope\n\
+             SERVICE=systemd-nspawn\n\
+             ROOT=/var/lib/machines/debian\n\
+             CLASS=container\n\
+             LEADER=4242\n",
+        );
+
+        assert_eq!(state.get("LEADER").unwrap(), "4242");
+        assert_eq!(state.get("ROOT").unwrap(), "/var/lib/machines/debian");
+        assert_eq!(state.get("SCOPE").unwrap(), "machine-debian.scope");
+        assert_eq!(state.len(), 6);
+    }
+}
@@ -0,0 +1,252 @@
+//! Podmanのコンテナをデバック対象にする
+//!
+//! libpodのREST APIで解決する。rootfulとrootlessのAPIソケットを順に試す
+
+use crate::container::{
+    self, Container, DockerContainerInspect, DockerContainerList, DockerGraphDriver, Runtime,
+};
+use crate::docker_client::DockerClient;
+use std::path::{Path, PathBuf};
+use std::{env, error, fmt};
+
+const ROOTFUL_PODMAN_SOCK: &str = "/run/podman/podman.sock";
+const ROOTLESS_PODMAN_SOCK: &str = "podman/podman.sock";
+const LIBPOD_API_PREFIX: &str = "/v3.0.0/libpod";
+
+#[derive(Debug)]
+pub enum Error {
+    SocketNotFound,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SocketNotFound => write!(f, "podman: API socket not found"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// 名前またはIDでPodmanのコンテナを解決する
+///
+/// コンテナを知っている最初のAPIソケットの結果を使う
+pub fn new(name_or_id: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    let mut last_error: Box<dyn std::error::Error> = Box::new(Error::SocketNotFound);
+
+    for socket in socket_candidates() {
+        if !socket.exists() {
+            continue;
+        }
+        match resolve(&socket, name_or_id) {
+            Ok(container) => return Ok(container),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// 試すAPIソケット。rootfulが先
+///
+/// injeshは通常sudoで実行され`XDG_RUNTIME_DIR`が引き継がれないため、`SUDO_UID`のランタイムディレクトリも試す
+fn socket_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from(ROOTFUL_PODMAN_SOCK)];
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        candidates.push(Path::new(&runtime_dir).join(ROOTLESS_PODMAN_SOCK));
+    }
+    if let Ok(uid) = env::var("SUDO_UID") {
+        candidates.push(Path::new("/run/user").join(uid).join(ROOTLESS_PODMAN_SOCK));
+    }
+
+    candidates
+}
+
+fn resolve(socket: &Path, name_or_id: &str) -> Result<Container, Box<dyn std::error::Error>> {
+    let inspect = inspect(socket, name_or_id)?;
+    container::check_running(&inspect.State)?;
+    let pid = verify_pid(&inspect.Id, inspect.State.Pid)?;
+
+    Ok(from_graph_driver(
+        socket == Path::new(ROOTFUL_PODMAN_SOCK),
+        &inspect.Id,
+        pid,
+        inspect.GraphDriver,
+    ))
+}
+
+/// Podmanではoverlay2互換のドライバを`overlay`と呼ぶ
+///
+/// rootlessのPodmanはユーザのmount namespaceでマージするため、`MergedDir`はホストから見えない。
+/// rootlessのソケットでは`/proc/<pid>/root`を使う
+fn from_graph_driver(
+    rootful: bool,
+    container_id: &str,
+    pid: u32,
+    graph_driver: DockerGraphDriver,
+) -> Container {
+    match (rootful, graph_driver.Name.as_str(), graph_driver.Data) {
+        (true, "overlay", Some(data)) => {
+            Container::from_graph_driver(Runtime::Podman, container_id, pid, data)
+        }
+        _ => Container::from_pid(Runtime::Podman, container_id, pid),
+    }
+}
+
+/// 名前とIDはlibpodが解決する。コンテナの一覧はDockerと同様にその代わりとして使う
+fn inspect(
+    socket: &Path,
+    name_or_id: &str,
+) -> Result<DockerContainerInspect, Box<dyn std::error::Error>> {
+    match inspect_container(socket, name_or_id) {
+        Ok(inspect) => Ok(inspect),
+        Err(_) => {
+            let id = convert_name_to_id(socket, name_or_id)?;
+            inspect_container(socket, &id)
+        }
+    }
+}
+
+fn inspect_container(
+    socket: &Path,
+    name_or_id: &str,
+) -> Result<DockerContainerInspect, Box<dyn std::error::Error>> {
+    DockerClient::unix(socket).get(
+        &format!("{}/containers/{}/json", LIBPOD_API_PREFIX, name_or_id),
+        &[],
+    )
+}
+
+/// libpodの名前のフィルタも部分一致のため、Dockerと同じ方法で名前またはIDを照合する
+fn convert_name_to_id(
+    socket: &Path,
+    name_or_id: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let containers: Vec<DockerContainerList> = DockerClient::unix(socket).get(
+        &format!("{}/containers/json", LIBPOD_API_PREFIX),
+        &[("all", "true")],
+    )?;
+
+    Ok(container::select_container_id(&containers, name_or_id)?)
+}
+
+/// `State.Pid`はコンテナのcgroupに属するか、その`conmon`の子プロセスである必要がある
+///
+/// cgroup v1のrootless Podmanはコンテナのcgroupを持たないため、`conmon`も確認する
+fn verify_pid(container_id: &str, pid: u32) -> Result<u32, Box<dyn std::error::Error>> {
+    if pid != 0
+        && (container::pid_belongs_to_container(pid, container_id)
+            || conmon_is_parent(pid, container_id))
+    {
+        return Ok(pid);
+    }
+
+    Err(container::Error::ContainerProcessNotFound)?
+}
+
+fn conmon_is_parent(pid: u32, container_id: &str) -> bool {
+    let parent_pid = match container::parent_pid(pid) {
+        Ok(parent_pid) => parent_pid,
+        Err(_) => return false,
+    };
+
+    match std::fs::read_to_string(format!("/proc/{}/cmdline", parent_pid)) {
+        Ok(cmdline) => cmdline.contains("conmon") && cmdline.contains(container_id),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, json};
+
+    const CONTAINER_ID: &str = "8f3a1c2b9d7e";
+
+    fn inspect_body(status: &str, running: bool) -> String {
+        format!(
+            r#"{{"Id":"{}","State":{{"Status":"{}","Running":{},"Paused":false,"Pid":0}},"GraphDriver":{{"Name":"overlay","Data":{{"LowerDir":"/l","UpperDir":"/u","MergedDir":"/m","WorkDir":"/w"}}}}}}"#,
+            CONTAINER_ID, status, running
+        )
+    }
+
+    #[test]
+    fn test_inspect_by_name() {
+        let (socket, handle) = test_server::serve(
+            "podman-name",
+            vec![
+                json(404, r#"{"message":"no such container"}"#),
+                json(
+                    200,
+                    &format!(r#"[{{"Id":"{}","Names":["web"]}}]"#, CONTAINER_ID),
+                ),
+                json(200, &inspect_body("running", true)),
+            ],
+        );
+
+        let inspect = inspect(&socket, "web").unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(inspect.Id, CONTAINER_ID);
+        assert_eq!(
+            inspect.GraphDriver.Data.unwrap().MergedDir,
+            PathBuf::from("/m")
+        );
+    }
+
+    #[test]
+    fn test_convert_name_to_id_overlapping_names() {
+        let (socket, handle) = test_server::serve(
+            "podman-overlapping",
+            vec![json(
+                200,
+                &format!(
+                    r#"[{{"Id":"1d2e3f4a5b6c","Names":["web-db"]}},{{"Id":"{}","Names":["web"]}}]"#,
+                    CONTAINER_ID
+                ),
+            )],
+        );
+
+        let id = convert_name_to_id(&socket, "web").unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(id, CONTAINER_ID);
+    }
+
+    #[test]
+    fn test_from_graph_driver_rootless() {
+        let graph_driver = || -> DockerGraphDriver {
+            serde_json::from_str(
+                r#"{"Name":"overlay","Data":{"LowerDir":"/l","UpperDir":"/u","MergedDir":"/m","WorkDir":"/w"}}"#,
+            )
+            .unwrap()
+        };
+
+        let rootful = from_graph_driver(true, CONTAINER_ID, std::process::id(), graph_driver());
+        let rootless = from_graph_driver(false, CONTAINER_ID, std::process::id(), graph_driver());
+
+        assert_eq!(rootful.fs_view(), container::FsView::MergedDir);
+        assert_ne!(rootless.fs_view(), container::FsView::MergedDir);
+        assert_eq!(
+            rootless.target_root(),
+            None,
+            "the test process shares the host mount namespace"
+        );
+    }
+
+    #[test]
+    fn test_resolve_stopped() {
+        let (socket, handle) = test_server::serve(
+            "podman-stopped",
+            vec![json(200, &inspect_body("exited", false))],
+        );
+
+        let err = resolve(&socket, CONTAINER_ID).unwrap_err();
+        handle.join().unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<container::Error>(),
+            Some(container::Error::ContainerNotRunning(status)) if status == "exited"
+        ));
+    }
+}
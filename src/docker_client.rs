@@ -0,0 +1,516 @@
+//! HTTP/1.1 client of the Docker Engine API.
+//!
+//! One connection is kept alive across requests. Bodies are read by
+//! `Content-Length` or chunked transfer encoding, and JSON responses are
+//! deserialized into the caller's types. Against dockerd the API version is
+//! negotiated on the first request, the same way the docker CLI does.
+//!
+//! libpod and LXD speak the same HTTP dialect and version their paths themselves,
+//! so they use `DockerClient::unix` which skips the negotiation.
+
+use crate::docker_host::{DockerHost, Stream};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+use std::{error, fmt};
+
+/// Newest API version injesh knows, used unless dockerd is older
+const API_VERSION: (u32, u32) = (1, 41);
+const TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HEADERS: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidResponse,
+    /// HTTP status and the message of the error body
+    Api(u16, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidResponse => write!(f, "docker_client: invalid response"),
+            Error::Api(status, message) => {
+                write!(f, "docker_client: API error {}: {}", status, message)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// `{"message": "..."}` of Docker and libpod, `{"error": "..."}` of LXD
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    error: Option<String>,
+}
+
+/// `GET /version`
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct VersionBody {
+    ApiVersion: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum ApiVersion {
+    /// paths are sent as given
+    Unversioned,
+    /// asked to dockerd before the first request
+    Negotiate,
+    /// paths are prefixed with `/v<version>`
+    Negotiated(String),
+}
+
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    /// names in lower case
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+}
+
+pub struct DockerClient {
+    host: DockerHost,
+    timeout: Option<Duration>,
+    connection: Option<BufReader<Box<dyn Stream>>>,
+    api_version: ApiVersion,
+}
+
+impl DockerClient {
+    /// Client of the dockerd found by `DockerHost::from_env`.
+    pub fn from_env() -> Result<DockerClient, Box<dyn std::error::Error>> {
+        Ok(Self::new(DockerHost::from_env()?))
+    }
+
+    pub fn new(host: DockerHost) -> DockerClient {
+        DockerClient {
+            host,
+            timeout: Some(TIMEOUT),
+            connection: None,
+            api_version: ApiVersion::Negotiate,
+        }
+    }
+
+    /// Client of a Docker compatible API on a Unix socket, without version negotiation.
+    pub fn unix(socket: &Path) -> DockerClient {
+        DockerClient {
+            api_version: ApiVersion::Unversioned,
+            ..Self::new(DockerHost::Unix(socket.to_path_buf()))
+        }
+    }
+
+    /// `GET`s the path and deserializes the JSON response.
+    pub fn get<T: DeserializeOwned>(
+        &mut self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        let response = self.request("GET", path, query)?;
+        Ok(serde_json::from_slice(response.body())?)
+    }
+
+    pub fn post(
+        &mut self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.request("POST", path, query)?;
+        Ok(())
+    }
+
+    /// Sends a request without a body, failing on any status but 2xx.
+    pub fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Response, Box<dyn std::error::Error>> {
+        let target = self.target(path, query)?;
+        let response = self.send(method, &target)?;
+
+        if !(200..300).contains(&response.status) {
+            Err(api_error(response.status, &response.body))?
+        }
+
+        Ok(response)
+    }
+
+    /// Sends a `GET` request and returns the body as it arrives,
+    /// for endless responses such as `/events`.
+    ///
+    /// The body is read over a connection of its own, which never times out.
+    pub fn stream(
+        &mut self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Box<dyn BufRead>, Box<dyn std::error::Error>> {
+        let target = self.target(path, query)?;
+        let mut connection = BufReader::new(self.host.connect(None)?);
+        write_request(connection.get_mut(), "GET", &target, &self.host_header())?;
+
+        let (status, headers) = read_head(&mut connection)?;
+        if !(200..300).contains(&status) {
+            let (body, _) = read_body(&mut connection, status, &headers)?;
+            Err(api_error(status, &body))?
+        }
+        match is_chunked(&headers) {
+            true => Ok(Box::new(BufReader::new(ChunkedReader::new(connection)))),
+            false => Ok(Box::new(connection)),
+        }
+    }
+
+    /// Path and query, prefixed with the negotiated API version.
+    fn target(
+        &mut self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        if self.api_version == ApiVersion::Negotiate {
+            self.api_version = ApiVersion::Negotiated(self.negotiate()?);
+        }
+
+        let mut target = match &self.api_version {
+            ApiVersion::Negotiated(version) => format!("/v{}{}", version, path),
+            _ => path.to_string(),
+        };
+        if !query.is_empty() {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(query)
+                .finish();
+            target = format!("{}?{}", target, query);
+        }
+
+        Ok(target)
+    }
+
+    /// Asks `/_ping`, or `/version` for dockerd older than 1.25 which lack the header.
+    fn negotiate(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let ping = self.send("GET", "/_ping")?;
+        let server_version = match ping.header("api-version") {
+            Some(version) => version.to_string(),
+            None => {
+                let version = self.send("GET", "/version")?;
+                serde_json::from_slice::<VersionBody>(version.body())?.ApiVersion
+            }
+        };
+
+        Ok(negotiate_version(&server_version))
+    }
+
+    /// Sends the request over the kept alive connection,
+    /// reconnecting once when dockerd has closed it meanwhile.
+    fn send(&mut self, method: &str, target: &str) -> Result<Response, Box<dyn std::error::Error>> {
+        let reused = self.connection.is_some();
+        match self.round_trip(method, target) {
+            // only requests without side effects are safe to send twice
+            Err(e) if reused && method == "GET" && e.downcast_ref::<io::Error>().is_some() => {
+                self.connection = None;
+                self.round_trip(method, target)
+            }
+            result => result,
+        }
+    }
+
+    fn round_trip(
+        &mut self,
+        method: &str,
+        target: &str,
+    ) -> Result<Response, Box<dyn std::error::Error>> {
+        let host_header = self.host_header();
+        let connection = match &mut self.connection {
+            Some(connection) => connection,
+            connection => connection.insert(BufReader::new(self.host.connect(self.timeout)?)),
+        };
+
+        let result = exchange(connection, method, target, &host_header);
+        match &result {
+            Ok((_, keep_alive)) if *keep_alive => {}
+            _ => self.connection = None,
+        }
+
+        result.map(|(response, _)| response)
+    }
+
+    fn host_header(&self) -> String {
+        match &self.host {
+            DockerHost::Tcp(address, _) => address.clone(),
+            DockerHost::Unix(_) => "localhost".to_string(),
+        }
+    }
+}
+
+/// Picks the older of the server's API version and `API_VERSION`.
+fn negotiate_version(server_version: &str) -> String {
+    let parsed = server_version
+        .split_once('.')
+        .and_then(|(major, minor)| Some((major.parse().ok()?, minor.parse().ok()?)));
+    let (major, minor) = match parsed {
+        Some(version) if version < API_VERSION => version,
+        _ => API_VERSION,
+    };
+
+    format!("{}.{}", major, minor)
+}
+
+fn write_request(
+    stream: &mut dyn Stream,
+    method: &str,
+    target: &str,
+    host: &str,
+) -> io::Result<()> {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: injesh\r\n",
+        method, target, host
+    );
+    if method == "POST" {
+        request.push_str("Content-Length: 0\r\n");
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes())?;
+    stream.flush()
+}
+
+/// One request and its response. Also tells whether the connection can be reused.
+fn exchange(
+    connection: &mut BufReader<Box<dyn Stream>>,
+    method: &str,
+    target: &str,
+    host: &str,
+) -> Result<(Response, bool), Box<dyn std::error::Error>> {
+    write_request(connection.get_mut(), method, target, host)?;
+    let (status, headers) = read_head(connection)?;
+    let (body, keep_alive) = read_body(connection, status, &headers)?;
+
+    Ok((
+        Response {
+            status,
+            headers,
+            body,
+        },
+        keep_alive,
+    ))
+}
+
+/// Reads the body by the headers. Also tells whether the connection can be reused.
+fn read_body<R: BufRead>(
+    connection: &mut R,
+    status: u16,
+    headers: &HashMap<String, String>,
+) -> Result<(Vec<u8>, bool), Box<dyn std::error::Error>> {
+    let mut keep_alive = !headers
+        .get("connection")
+        .is_some_and(|connection| connection.eq_ignore_ascii_case("close"));
+    let mut body = Vec::new();
+    let content_length = headers
+        .get("content-length")
+        .map(|length| length.parse::<usize>())
+        .transpose()
+        .map_err(|_| Error::InvalidResponse)?;
+
+    if status == 204 || status == 304 || (100..200).contains(&status) {
+        // no body
+    } else if is_chunked(headers) {
+        ChunkedReader::new(&mut *connection).read_to_end(&mut body)?;
+    } else if let Some(length) = content_length {
+        body.resize(length, 0);
+        connection.read_exact(&mut body)?;
+    } else {
+        // the body ends when the connection does
+        connection.read_to_end(&mut body)?;
+        keep_alive = false;
+    }
+
+    Ok((body, keep_alive))
+}
+
+fn is_chunked(headers: &HashMap<String, String>) -> bool {
+    headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+}
+
+/// Takes the message out of the error body.
+fn api_error(status: u16, body: &[u8]) -> Error {
+    let message = serde_json::from_slice::<ErrorBody>(body)
+        .ok()
+        .and_then(|body| body.message.or(body.error))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string());
+
+    Error::Api(status, message)
+}
+
+/// Reads the status line and the headers.
+fn read_head<R: BufRead>(
+    reader: &mut R,
+) -> Result<(u16, HashMap<String, String>), Box<dyn std::error::Error>> {
+    let mut head = Vec::new();
+    loop {
+        let length = reader.read_until(b'\n', &mut head)?;
+        if length == 0 {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof))?
+        }
+        if head.ends_with(b"\r\n\r\n") || head == b"\r\n" {
+            break;
+        }
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut response = httparse::Response::new(&mut headers);
+    if !response.parse(&head)?.is_complete() {
+        Err(Error::InvalidResponse)?
+    }
+    let status = response.code.ok_or(Error::InvalidResponse)?;
+    let headers = response
+        .headers
+        .iter()
+        .map(|header| {
+            (
+                header.name.to_ascii_lowercase(),
+                String::from_utf8_lossy(header.value).trim().to_string(),
+            )
+        })
+        .collect();
+
+    Ok((status, headers))
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`.
+struct ChunkedReader<R: BufRead> {
+    inner: R,
+    /// bytes left in the current chunk
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            // `<size in hex>[;extensions]`
+            let line = self.read_line()?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            self.remaining = usize::from_str_radix(size, 16)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size"))?;
+            if self.remaining == 0 {
+                // skip the trailers up to the empty line
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let length = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..length])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            // CRLF after the chunk data
+            self.read_line()?;
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+
+    #[test]
+    fn test_keep_alive_and_chunked() {
+        let (socket, handle) = test_server::serve(
+            "docker-client-chunked",
+            vec![
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 6\r\n{\"Id\":\r\n7;ext=1\r\n\"abcd\"}\r\n0\r\n\r\n"
+                    .to_string(),
+                test_server::json(404, r#"{"message":"No such container"}"#),
+            ],
+        );
+
+        let mut client = DockerClient::unix(&socket);
+        let body: HashMap<String, String> = client.get("/containers/abcd/json", &[]).unwrap();
+        let err = client.get::<HashMap<String, String>>("/containers/efgh/json", &[]);
+        let connections = handle.join().unwrap();
+
+        assert_eq!(body.get("Id").unwrap(), "abcd");
+        assert!(matches!(
+            err.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::Api(404, message)) if message == "No such container"
+        ));
+        // both went over the one connection
+        assert_eq!(
+            connections,
+            vec![vec![
+                "GET /containers/abcd/json HTTP/1.1",
+                "GET /containers/efgh/json HTTP/1.1",
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let (socket, handle) = test_server::serve(
+            "docker-client-negotiate",
+            vec![
+                "HTTP/1.1 200 OK\r\nApi-Version: 1.40\r\nContent-Length: 2\r\n\r\nOK".to_string(),
+                test_server::json(200, "[]"),
+            ],
+        );
+
+        let mut client = DockerClient::new(DockerHost::Unix(socket));
+        let containers: Vec<HashMap<String, String>> =
+            client.get("/containers/json", &[("all", "true")]).unwrap();
+        let connections = handle.join().unwrap();
+
+        assert!(containers.is_empty());
+        assert_eq!(
+            connections,
+            vec![vec![
+                "GET /_ping HTTP/1.1",
+                "GET /v1.40/containers/json?all=true HTTP/1.1",
+            ]]
+        );
+        assert_eq!(negotiate_version("1.43"), "1.41");
+    }
+}
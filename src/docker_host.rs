@@ -0,0 +1,350 @@
+//! Endpoint of dockerd, resolved the way the docker CLI does.
+//!
+//! In order: `DOCKER_HOST` (with `DOCKER_TLS_VERIFY` and `DOCKER_CERT_PATH`),
+//! the context given by `DOCKER_CONTEXT` or selected with `docker context use`,
+//! and finally the default socket, or the rootless one when only that exists.
+
+use crate::user;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use serde::Deserialize;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, error, fmt};
+
+const DEFAULT_DOCKER_SOCK: &str = "/var/run/docker.sock";
+const ROOTLESS_DOCKER_SOCK: &str = "docker.sock";
+const DEFAULT_CONTEXT: &str = "default";
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHost(String),
+    UnsupportedScheme(String),
+    ContextNotFound(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidHost(host) => write!(f, "docker_host: invalid docker host {}", host),
+            Error::UnsupportedScheme(host) => {
+                write!(f, "docker_host: unsupported docker host {}", host)
+            }
+            Error::ContextNotFound(name) => {
+                write!(f, "docker_host: docker context {} not found", name)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Byte stream to dockerd, whichever transport it is
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+#[derive(Debug, PartialEq)]
+pub enum DockerHost {
+    Unix(PathBuf),
+    /// `host:port`
+    Tcp(String, Option<Tls>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Tls {
+    ca: Option<PathBuf>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    verify: bool,
+}
+
+impl Tls {
+    /// Uses `ca.pem`, `cert.pem` and `key.pem` of the directory when they exist.
+    fn from_dir(dir: &Path, verify: bool) -> Tls {
+        let pem = |name: &str| Some(dir.join(name)).filter(|path| path.exists());
+        Tls {
+            ca: pem("ca.pem"),
+            cert: pem("cert.pem"),
+            key: pem("key.pem"),
+            verify,
+        }
+    }
+}
+
+/// `~/.docker/config.json`
+#[derive(Debug, Deserialize)]
+struct DockerConfig {
+    #[serde(rename = "currentContext", default)]
+    current_context: Option<String>,
+}
+
+/// `~/.docker/contexts/meta/<sha256 of the name>/meta.json`
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ContextMeta {
+    Endpoints: ContextEndpoints,
+}
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ContextEndpoints {
+    docker: ContextEndpoint,
+}
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+struct ContextEndpoint {
+    Host: String,
+    #[serde(default)]
+    SkipTLSVerify: bool,
+}
+
+impl DockerHost {
+    pub fn from_env() -> Result<DockerHost, Box<dyn std::error::Error>> {
+        let config_dir = config_dir()?;
+
+        if let Some(host) = non_empty_var("DOCKER_HOST") {
+            let verify = non_empty_var("DOCKER_TLS_VERIFY").is_some();
+            let tls = match verify || non_empty_var("DOCKER_TLS").is_some() {
+                true => {
+                    let cert_path = non_empty_var("DOCKER_CERT_PATH")
+                        .map(PathBuf::from)
+                        .unwrap_or_else(|| config_dir.clone());
+                    Some(Tls::from_dir(&cert_path, verify))
+                }
+                false => None,
+            };
+            return Ok(Self::parse(&host, tls)?);
+        }
+
+        let context = match non_empty_var("DOCKER_CONTEXT") {
+            Some(context) => Some(context),
+            None => std::fs::read_to_string(config_dir.join("config.json"))
+                .ok()
+                .and_then(|config| serde_json::from_str::<DockerConfig>(&config).ok())
+                .and_then(|config| config.current_context),
+        };
+        match context {
+            Some(context) if context != DEFAULT_CONTEXT => {
+                Self::from_context(&config_dir, &context)
+            }
+            _ => Ok(Self::default_socket()),
+        }
+    }
+
+    /// Parses `unix:///path/to/docker.sock` or `tcp://host:port`.
+    pub fn parse(host: &str, tls: Option<Tls>) -> Result<DockerHost, Error> {
+        if let Some(path) = host.strip_prefix("unix://") {
+            return Ok(DockerHost::Unix(PathBuf::from(path)));
+        }
+        if let Some(address) = host.strip_prefix("tcp://") {
+            let address = address.trim_end_matches('/');
+            if address.is_empty() {
+                Err(Error::InvalidHost(host.to_string()))?
+            }
+            // dockerd listens on 2376 with TLS, 2375 without
+            let address = match address.contains(':') {
+                true => address.to_string(),
+                false if tls.is_some() => format!("{}:2376", address),
+                false => format!("{}:2375", address),
+            };
+            return Ok(DockerHost::Tcp(address, tls));
+        }
+
+        Err(Error::UnsupportedScheme(host.to_string()))
+    }
+
+    fn from_context(
+        config_dir: &Path,
+        name: &str,
+    ) -> Result<DockerHost, Box<dyn std::error::Error>> {
+        let digest = context_digest(name);
+        let meta = std::fs::read_to_string(
+            config_dir
+                .join("contexts/meta")
+                .join(&digest)
+                .join("meta.json"),
+        )
+        .map_err(|_| Error::ContextNotFound(name.to_string()))?;
+        let endpoint = serde_json::from_str::<ContextMeta>(&meta)?.Endpoints.docker;
+
+        let tls_dir = config_dir.join("contexts/tls").join(&digest).join("docker");
+        let tls = match tls_dir.exists() {
+            true => Some(Tls::from_dir(&tls_dir, !endpoint.SkipTLSVerify)),
+            false => None,
+        };
+
+        Ok(Self::parse(&endpoint.Host, tls)?)
+    }
+
+    /// `/var/run/docker.sock`, or the socket of rootless Docker if only that one exists.
+    fn default_socket() -> DockerHost {
+        let default_socket = PathBuf::from(DEFAULT_DOCKER_SOCK);
+        if default_socket.exists() {
+            return DockerHost::Unix(default_socket);
+        }
+
+        // injesh usually runs through sudo, which drops `XDG_RUNTIME_DIR`
+        let mut candidates = Vec::new();
+        if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+            candidates.push(Path::new(&runtime_dir).join(ROOTLESS_DOCKER_SOCK));
+        }
+        if let Ok(uid) = env::var("SUDO_UID") {
+            candidates.push(Path::new("/run/user").join(uid).join(ROOTLESS_DOCKER_SOCK));
+        }
+
+        DockerHost::Unix(
+            candidates
+                .into_iter()
+                .find(|socket| socket.exists())
+                .unwrap_or(default_socket),
+        )
+    }
+
+    /// Connects to dockerd. `timeout` applies to connecting, and to every read and write.
+    pub fn connect(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Box<dyn Stream>, Box<dyn std::error::Error>> {
+        match self {
+            DockerHost::Unix(socket) => {
+                let stream = UnixStream::connect(socket)?;
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)?;
+                Ok(Box::new(stream))
+            }
+            DockerHost::Tcp(address, None) => Ok(Box::new(connect_tcp(address, timeout)?)),
+            DockerHost::Tcp(address, Some(tls)) => {
+                let mut connector = SslConnector::builder(SslMethod::tls())?;
+                if let Some(ca) = &tls.ca {
+                    connector.set_ca_file(ca)?;
+                }
+                if let (Some(cert), Some(key)) = (&tls.cert, &tls.key) {
+                    connector.set_certificate_chain_file(cert)?;
+                    connector.set_private_key_file(key, openssl::ssl::SslFiletype::PEM)?;
+                }
+                if !tls.verify {
+                    connector.set_verify(SslVerifyMode::NONE);
+                }
+
+                let hostname = address
+                    .rsplit_once(':')
+                    .map_or(address.as_str(), |(hostname, _)| hostname);
+                let stream = connect_tcp(address, timeout)?;
+                let stream = connector
+                    .build()
+                    .configure()?
+                    .verify_hostname(tls.verify)
+                    .connect(hostname, stream)?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+fn connect_tcp(address: &str, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let stream = match timeout {
+        Some(timeout) => {
+            let mut last_error = None;
+            let mut connected = None;
+            for address in address.to_socket_addrs()? {
+                match TcpStream::connect_timeout(&address, timeout) {
+                    Ok(stream) => {
+                        connected = Some(stream);
+                        break;
+                    }
+                    Err(e) => last_error = Some(e),
+                }
+            }
+            match (connected, last_error) {
+                (Some(stream), _) => stream,
+                (None, Some(e)) => return Err(e),
+                (None, None) => return Err(std::io::ErrorKind::AddrNotAvailable.into()),
+            }
+        }
+        None => TcpStream::connect(address)?,
+    };
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    Ok(stream)
+}
+
+/// `$DOCKER_CONFIG`, or `~/.docker` of the user who ran sudo
+fn config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match non_empty_var("DOCKER_CONFIG") {
+        Some(config_dir) => Ok(PathBuf::from(config_dir)),
+        None => Ok(Path::new(&user::home_dir()?).join(".docker")),
+    }
+}
+
+/// Contexts are stored under the hex encoded SHA-256 of their name.
+fn context_digest(name: &str) -> String {
+    openssl::sha::sha256(name.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn non_empty_var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            DockerHost::parse("unix:///run/user/1000/docker.sock", None).unwrap(),
+            DockerHost::Unix(PathBuf::from("/run/user/1000/docker.sock"))
+        );
+        assert_eq!(
+            DockerHost::parse("tcp://10.0.0.5", None).unwrap(),
+            DockerHost::Tcp("10.0.0.5:2375".to_string(), None)
+        );
+        assert!(matches!(
+            DockerHost::parse("ssh://user@host", None),
+            Err(Error::UnsupportedScheme(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_context() {
+        let config_dir =
+            std::env::temp_dir().join(format!("injesh-docker-config-{}", std::process::id()));
+        let digest = context_digest("remote");
+        assert_eq!(
+            digest,
+            "b71199ebd070b36beab7317920c2c2f1d777df8d05e5527d8458fda57cb17a7a"
+        );
+        let meta_dir = config_dir.join("contexts/meta").join(&digest);
+        let tls_dir = config_dir.join("contexts/tls").join(&digest).join("docker");
+        std::fs::create_dir_all(&meta_dir).unwrap();
+        std::fs::create_dir_all(&tls_dir).unwrap();
+        std::fs::write(
+            meta_dir.join("meta.json"),
+            r#"{"Name":"remote","Metadata":{},"Endpoints":{"docker":{"Host":"tcp://build.example.com:2376","SkipTLSVerify":false}}}"#,
+        )
+        .unwrap();
+        std::fs::write(tls_dir.join("ca.pem"), "").unwrap();
+
+        let host = DockerHost::from_context(&config_dir, "remote").unwrap();
+        std::fs::remove_dir_all(&config_dir).unwrap();
+
+        assert_eq!(
+            host,
+            DockerHost::Tcp(
+                "build.example.com:2376".to_string(),
+                Some(Tls {
+                    ca: Some(tls_dir.join("ca.pem")),
+                    cert: None,
+                    key: None,
+                    verify: true,
+                })
+            )
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Unixソケット上の最小限のgRPCクライアント
+//!
+//! containerdとCRIランタイムへの問い合わせに必要な、unary呼び出しとprotobufのエンコード・デコードのみ実装する
+
+use bytes::Bytes;
+use std::path::Path;
+use std::{error, fmt};
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidResponse,
+    Decode,
+    Status(u32, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidResponse => write!(f, "grpc: invalid response"),
+            Error::Decode => write!(f, "grpc: failed to decode protobuf message"),
+            Error::Status(code, message) => {
+                write!(f, "grpc: call failed with status {}: {}", code, message)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// unaryのgRPCメソッド`path`(`/containerd.services.tasks.v1.Tasks/Get`など)を呼び出し、エンコードされたレスポンスを返す
+///
+/// `metadata`は`containerd-namespace`などのリクエストヘッダとして送る
+pub fn unary(
+    socket: &Path,
+    path: &str,
+    metadata: &[(&str, &str)],
+    message: Vec<u8>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+
+    runtime.block_on(unary_async(socket, path, metadata, message))
+}
+
+async fn unary_async(
+    socket: &Path,
+    path: &str,
+    metadata: &[(&str, &str)],
+    message: Vec<u8>,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let stream = tokio::net::UnixStream::connect(socket).await?;
+    let (client, connection) = h2::client::handshake(stream).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let mut request = http::Request::builder()
+        .method("POST")
+        .uri(format!("http://localhost{}", path))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers");
+    for (key, value) in metadata {
+        request = request.header(*key, *value);
+    }
+    let request = request.body(())?;
+
+    let mut client = client.ready().await?;
+    let (response, mut send_stream) = client.send_request(request, false)?;
+    send_stream.send_data(Bytes::from(frame(&message)), true)?;
+
+    let response = response.await?;
+    let (parts, mut body) = response.into_parts();
+    if parts.status != http::StatusCode::OK {
+        Err(Error::InvalidResponse)?
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+    }
+
+    // エラーはtrailer、"Trailers-Only"のレスポンスではヘッダで返される
+    let trailers = body.trailers().await?;
+    let status_headers = trailers.as_ref().unwrap_or(&parts.headers);
+    check_status(status_headers)?;
+
+    unframe(&data)
+}
+
+fn check_status(headers: &http::HeaderMap) -> Result<(), Error> {
+    let code = match headers.get("grpc-status") {
+        Some(code) => code
+            .to_str()
+            .ok()
+            .and_then(|code| code.parse::<u32>().ok())
+            .ok_or(Error::InvalidResponse)?,
+        None => Err(Error::InvalidResponse)?,
+    };
+    if code != 0 {
+        let message = headers
+            .get("grpc-message")
+            .and_then(|message| message.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        Err(Error::Status(code, message))?
+    }
+
+    Ok(())
+}
+
+/// メッセージの前に、非圧縮のフラグと長さを付ける
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(message.len() + 5);
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend_from_slice(message);
+    framed
+}
+
+/// `frame`で付けた前置きを取り除く
+pub fn unframe(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if data.len() < 5 || data[0] != 0 {
+        Err(Error::InvalidResponse)?
+    }
+    let length = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let message = data.get(5..5 + length).ok_or(Error::InvalidResponse)?;
+
+    Ok(message.to_vec())
+}
+
+/// protobufのメッセージを組み立てる
+///
+/// ```ignore
+/// let mut request = grpc::Encoder::new();
+/// request.string(1, "container_id");
+/// let bytes = request.finish();
+/// ```
+#[derive(Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Encoder {
+        Encoder { buf: Vec::new() }
+    }
+
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+        self
+    }
+
+    pub fn uint(&mut self, field: u32, value: u64) -> &mut Self {
+        self.key(field, 0);
+        self.varint(value);
+        self
+    }
+
+    pub fn bool(&mut self, field: u32, value: bool) -> &mut Self {
+        self.uint(field, value as u64)
+    }
+
+    /// `map<string, string>`のフィールドの1つのエントリをエンコードする
+    pub fn map_entry(&mut self, field: u32, key: &str, value: &str) -> &mut Self {
+        let entry = Encoder::new().string(1, key).string(2, value).finish();
+        self.bytes(field, &entry)
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+}
+
+/// デコードしたprotobufのメッセージのフィールド
+#[derive(Debug, PartialEq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    pub fn as_str(&self) -> Result<&'a str, Error> {
+        match self {
+            Value::Bytes(bytes) => std::str::from_utf8(bytes).map_err(|_| Error::Decode),
+            _ => Err(Error::Decode),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&'a [u8], Error> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(Error::Decode),
+        }
+    }
+
+    pub fn as_u64(&self) -> Result<u64, Error> {
+        match self {
+            Value::Varint(value) => Ok(*value),
+            _ => Err(Error::Decode),
+        }
+    }
+}
+
+/// protobufのメッセージを、エンコードされた順に`(フィールド番号, 値)`の組に分ける
+pub fn decode(mut buf: &[u8]) -> Result<Vec<(u32, Value<'_>)>, Error> {
+    let mut fields = Vec::new();
+
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf)?;
+        let field = (key >> 3) as u32;
+        let value = match key & 0x7 {
+            0 => Value::Varint(read_varint(&mut buf)?),
+            1 => {
+                buf = buf.get(8..).ok_or(Error::Decode)?;
+                Value::Fixed
+            }
+            2 => {
+                let length = read_varint(&mut buf)? as usize;
+                let bytes = buf.get(..length).ok_or(Error::Decode)?;
+                buf = &buf[length..];
+                Value::Bytes(bytes)
+            }
+            5 => {
+                buf = buf.get(4..).ok_or(Error::Decode)?;
+                Value::Fixed
+            }
+            _ => Err(Error::Decode)?,
+        };
+        fields.push((field, value));
+    }
+
+    Ok(fields)
+}
+
+/// `map<string, string>`のエントリをデコードする
+pub fn decode_map_entry(entry: &[u8]) -> Result<(String, String), Error> {
+    let mut key = String::new();
+    let mut value = String::new();
+    for (field, v) in decode(entry)? {
+        match field {
+            1 => key = v.as_str()?.to_string(),
+            2 => value = v.as_str()?.to_string(),
+            _ => {}
+        }
+    }
+
+    Ok((key, value))
+}
+
+fn read_varint(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first().ok_or(Error::Decode)?;
+        *buf = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(Error::Decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let message = Encoder::new().string(1, "abcd").string(2, "").finish();
+        let fields = decode(&message).unwrap();
+
+        assert_eq!(
+            fields,
+            vec![(1, Value::Bytes(b"abcd")), (2, Value::Bytes(b""))]
+        );
+        assert_eq!(
+            decode(&[0x18, 0xac, 0x02]).unwrap(),
+            vec![(3, Value::Varint(300))]
+        );
+    }
+
+    #[test]
+    fn test_frame() {
+        let framed = frame(b"abc");
+        assert_eq!(framed, vec![0, 0, 0, 0, 3, b'a', b'b', b'c']);
+        assert_eq!(unframe(&framed).unwrap(), b"abc".to_vec());
+    }
+}
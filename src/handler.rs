@@ -3,6 +3,9 @@ use crate::cmd::exec::ExecStruct;
 use crate::cmd::init::InitStruct;
 use crate::cmd::launch::LaunchStruct;
 use crate::cmd::list::ListStruct;
+use crate::cmd::ns::NsStruct;
+use crate::cmd::session;
+use crate::cmd::watch::WatchStruct;
 use crate::command::SubCommand;
 use crate::image_downloader::Downloader;
 use crate::setting;
@@ -51,16 +54,44 @@ where
             SubCommand::Exec(exec_args) => {
                 let exec = ExecStruct::new();
                 match exec.exec(exec_args) {
-                    Ok(_) => {}
-                    Err(e) => println!("execute exec command error: {:?}", e),
+                    Ok(code) => std::process::exit(code),
+                    Err(e) => {
+                        eprintln!("execute exec command error: {}", e);
+                        // docker execと同様に、injesh自身のエラーは125で終了する
+                        std::process::exit(session::EXIT_FAILED)
+                    }
                 }
             }
             SubCommand::File(_) => println!("TODO: file sub command"),
             SubCommand::Launch(launch_args) => {
                 let launch = LaunchStruct::new();
                 match launch.launch(launch_args) {
+                    Ok(code) => std::process::exit(code),
+                    Err(e) => {
+                        eprintln!("execute launch command error: {}", e);
+                        // docker execと同様に、injesh自身のエラーは125で終了する
+                        std::process::exit(session::EXIT_FAILED)
+                    }
+                }
+            }
+            SubCommand::Ns(ns_args) => {
+                let ns = NsStruct::new();
+                match ns.ns(ns_args) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("execute ns command error: {}", e);
+                        std::process::exit(session::EXIT_FAILED)
+                    }
+                }
+            }
+            SubCommand::Watch(watch_args) => {
+                let watch = WatchStruct::new();
+                match watch.watch(watch_args) {
                     Ok(_) => {}
-                    Err(e) => println!("execute launch command error: {:?}", e),
+                    Err(e) => {
+                        eprintln!("execute watch command error: {}", e);
+                        std::process::exit(session::EXIT_FAILED)
+                    }
                 }
             }
         }
@@ -1,8 +1,19 @@
+pub mod cgroup;
 pub mod cmd;
 mod container;
+mod container_containerd;
+mod container_cri;
+mod container_lxd;
+mod container_machined;
+mod container_podman;
+mod docker_client;
+mod docker_host;
+mod grpc;
 pub mod handler;
 mod image;
 pub mod parser;
+mod picker;
+mod security;
 pub mod setting;
 pub mod setting_yaml;
 mod user;
@@ -12,6 +23,8 @@ pub mod image_downloader;
 pub mod image_downloader_lxd;
 
 mod test_image;
+#[cfg(test)]
+mod test_server;
 
 pub mod command;
 pub mod namespace;
@@ -1,9 +1,11 @@
 use clap::Parser;
+use injesh::cmd::session;
 use injesh::command;
 use injesh::handler::{self, Handler};
 use injesh::image_downloader_lxd;
 use injesh::parser;
 use injesh::setting_yaml;
+use std::error::Error;
 
 fn main() {
     let args: parser::Cli = parser::Cli::parse();
@@ -12,13 +14,13 @@ fn main() {
             let init_command = command::SubCommand::Init::<
                 image_downloader_lxd::Downloader,
                 setting_yaml::YamlReaderWriter,
-            >(parser::initialize_init().unwrap());
+            >(initialize(parser::initialize_init()));
             let mut handler = handler::HandlerStruct::new(init_command);
             handler.run();
         }
         parser::Action::Launch(launch) => {
             let launch_command =
-                command::SubCommand::Launch(parser::initialize_launch(launch).unwrap());
+                command::SubCommand::Launch(initialize(parser::initialize_launch(launch)));
             let mut handler = handler::HandlerStruct::new(launch_command);
             handler.run();
         }
@@ -26,7 +28,7 @@ fn main() {
             let exec_command = command::SubCommand::Exec::<
                 image_downloader_lxd::Downloader,
                 setting_yaml::YamlReaderWriter,
-            >(parser::initialize_exec(exec).unwrap());
+            >(initialize(parser::initialize_exec(exec)));
             let mut handler = handler::HandlerStruct::new(exec_command);
             handler.run();
         }
@@ -34,7 +36,7 @@ fn main() {
             let list_command = command::SubCommand::List::<
                 image_downloader_lxd::Downloader,
                 setting_yaml::YamlReaderWriter,
-            >(parser::initialize_list().unwrap());
+            >(initialize(parser::initialize_list()));
             let mut handler = handler::HandlerStruct::new(list_command);
             handler.run();
         }
@@ -42,17 +44,32 @@ fn main() {
             let delete_command = command::SubCommand::Delete::<
                 image_downloader_lxd::Downloader,
                 setting_yaml::YamlReaderWriter,
-            >(parser::initialize_delete(delete).unwrap());
+            >(initialize(parser::initialize_delete(delete)));
             let mut handler = handler::HandlerStruct::new(delete_command);
             handler.run();
         }
+        parser::Action::Ns(ns) => {
+            let ns_command = command::SubCommand::Ns::<
+                image_downloader_lxd::Downloader,
+                setting_yaml::YamlReaderWriter,
+            >(initialize(parser::initialize_ns(ns)));
+            let mut handler = handler::HandlerStruct::new(ns_command);
+            handler.run();
+        }
+        parser::Action::Watch(watch) => {
+            let watch_command = command::SubCommand::Watch::<_, setting_yaml::YamlReaderWriter>(
+                initialize(parser::initialize_watch(watch)),
+            );
+            let mut handler = handler::HandlerStruct::new(watch_command);
+            handler.run();
+        }
         parser::Action::File(file) => match file.action {
             parser::FileAction::Pull(pull) => {
                 let file_pull_command = command::SubCommand::File::<
                     image_downloader_lxd::Downloader,
                     setting_yaml::YamlReaderWriter,
                 >(command::FileSubCommand::Pull(
-                    parser::initialize_file_pull(pull).unwrap(),
+                    initialize(parser::initialize_file_pull(pull)),
                 ));
                 let mut handler = handler::HandlerStruct::new(file_pull_command);
                 handler.run();
@@ -62,7 +79,7 @@ fn main() {
                     image_downloader_lxd::Downloader,
                     setting_yaml::YamlReaderWriter,
                 >(command::FileSubCommand::Push(
-                    parser::initialize_file_push(push).unwrap(),
+                    initialize(parser::initialize_file_push(push)),
                 ));
                 let mut handler = handler::HandlerStruct::new(file_push_command);
                 handler.run();
@@ -70,3 +87,14 @@ fn main() {
         },
     };
 }
+
+/// コマンドの初期化に失敗した場合は、injesh自身のエラーとして終了する
+fn initialize<T>(result: Result<T, Box<dyn Error>>) -> T {
+    match result {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("injesh: {}", e);
+            std::process::exit(session::EXIT_FAILED)
+        }
+    }
+}
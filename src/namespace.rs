@@ -1,65 +1,460 @@
+use crate::container;
+
+use nix::errno::Errno;
 use nix::sched::{setns, CloneFlags};
-use std::fs::File;
-use std::os::unix::io::AsRawFd;
+use nix::sys::utsname::uname;
+use nix::unistd::{setgroups, setresgid, setresuid, Gid, Uid};
+use serde::Serialize;
+use std::fs::{self, File};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::{error, fmt};
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownNamespace(String),
+    MountNamespaceNotSelectable,
+    UserNamespaceNotSelectable,
+    InvalidIdMap(String),
+    MountNamespaceNotOpened,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownNamespace(kind) => write!(f, "namespace: unknown namespace {}", kind),
+            Error::MountNamespaceNotSelectable => write!(
+                f,
+                "namespace: mnt cannot be chosen with --ns or --no-ns, use --mount-ns of launch to join it"
+            ),
+            Error::UserNamespaceNotSelectable => write!(
+                f,
+                "namespace: user cannot be chosen with --ns or --no-ns, it is joined whenever the target's differs from the host's"
+            ),
+            Error::InvalidIdMap(file) => write!(f, "namespace: invalid {}", file),
+            Error::MountNamespaceNotOpened => {
+                write!(f, "namespace: mount namespace of the target is not opened")
+            }
+        }
+    }
+}
 
-/// プロセスのnamespaceファイルディスクリプタを管理する構造体
+impl error::Error for Error {}
+
+/// デバック対象コンテナと共有できるnamespaceの種類
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Net,
+    Cgroup,
+    Ipc,
+    Pid,
+    Uts,
+}
+
+/// 指定がない場合に共有するnamespace。この順番でsetnsする
+pub const DEFAULT_KINDS: [Kind; 5] = [Kind::Net, Kind::Cgroup, Kind::Ipc, Kind::Pid, Kind::Uts];
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Net => write!(f, "net"),
+            Kind::Cgroup => write!(f, "cgroup"),
+            Kind::Ipc => write!(f, "ipc"),
+            Kind::Pid => write!(f, "pid"),
+            Kind::Uts => write!(f, "uts"),
+        }
+    }
+}
+
+impl std::str::FromStr for Kind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "net" => Ok(Kind::Net),
+            "cgroup" => Ok(Kind::Cgroup),
+            "ipc" => Ok(Kind::Ipc),
+            "pid" => Ok(Kind::Pid),
+            "uts" => Ok(Kind::Uts),
+            // 専用の方法で入るnamespace
+            "mnt" => Err(Error::MountNamespaceNotSelectable),
+            "user" => Err(Error::UserNamespaceNotSelectable),
+            _ => Err(Error::UnknownNamespace(s.to_string())),
+        }
+    }
+}
+
+/// `--ns`と`--no-ns`で指定された、共有するnamespaceの選択
+#[derive(Debug, Default, PartialEq)]
+pub struct Selection {
+    /// 共有するnamespace。空の場合は元の選択のまま
+    only: Vec<Kind>,
+    /// 共有しないnamespace
+    except: Vec<Kind>,
+}
+
+impl Selection {
+    /// `--ns`と`--no-ns`の値から作成する
+    pub fn parse(only: &[String], except: &[String]) -> Result<Selection, Error> {
+        Ok(Selection {
+            only: only
+                .iter()
+                .map(|kind| kind.parse())
+                .collect::<Result<_, _>>()?,
+            except: except
+                .iter()
+                .map(|kind| kind.parse())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// 指定がなかったかどうか
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.except.is_empty()
+    }
+
+    /// `base`に選択を適用し、共有するnamespaceを`DEFAULT_KINDS`の順で返す
+    pub fn apply(&self, base: &[Kind]) -> Vec<Kind> {
+        let selected = match self.only.is_empty() {
+            true => base,
+            false => &self.only,
+        };
+        DEFAULT_KINDS
+            .iter()
+            .filter(|kind| selected.contains(kind) && !self.except.contains(kind))
+            .copied()
+            .collect()
+    }
+}
+
+/// pidfdを渡して複数のnamespaceに一度に入れるようになったカーネルのバージョン
+const PIDFD_SETNS_VERSION: (u32, u32) = (5, 8);
+
+impl Kind {
+    fn clone_flag(&self) -> CloneFlags {
+        match self {
+            Kind::Net => CloneFlags::CLONE_NEWNET,
+            Kind::Cgroup => CloneFlags::CLONE_NEWCGROUP,
+            Kind::Ipc => CloneFlags::CLONE_NEWIPC,
+            Kind::Pid => CloneFlags::CLONE_NEWPID,
+            Kind::Uts => CloneFlags::CLONE_NEWUTS,
+        }
+    }
+}
+
+/// デバック対象プロセスのnamespaceに入るためのファイルディスクリプタを管理する構造体
+///
+/// 5.8以降のカーネルではpidfdだけを持ち、`setns(pidfd, flags)`で一度に入る。
+/// それより前のカーネルでは`/proc/<PID>/ns/*`を開いておき、1つずつ入る
 pub struct Ns {
-    net: File,
-    cgroup: File,
-    ipc: File,
-    pid: File,
-    user: File,
-    mnt: File,
-    uts: File,
+    pidfd: Option<File>,
+    files: Vec<(Kind, File)>,
+    /// デバック対象のuser namespaceがホストと異なる場合(userns-remap、rootless Docker)に入る
+    join_user: bool,
+    /// pidfdを使わない場合の`/proc/<PID>/ns/user`
+    user: Option<File>,
+    /// pidfdを使わない場合の`/proc/<PID>/ns/mnt`
+    mnt: Option<File>,
 }
 
 impl Ns {
-    pub fn new(container_pid: u32) -> Result<Ns, Box<dyn std::error::Error>> {
+    /// PIDが`container_pid`で、起動時刻が`start_time`のプロセスのnamespaceを開く
+    ///
+    /// コンテナの特定から開くまでの間にプロセスが終了し、PIDが再利用されていた場合はエラー
+    pub fn new(container_pid: u32, start_time: u64) -> Result<Ns, Box<dyn std::error::Error>> {
+        let pidfd = match pidfd_open(container_pid) {
+            Ok(pidfd) => Some(pidfd),
+            // 5.3より前のカーネル
+            Err(Errno::ENOSYS) => None,
+            Err(Errno::ESRCH) => Err(container::Error::ProcessNotFound(container_pid))?,
+            Err(e) => Err(e)?,
+        };
+        let join_user = !is_same_namespace(container_pid, "user")?;
+        // pidfdは開いた時点のプロセスを指し続けるため、ここで確認すればその後にPIDが再利用されても問題ない
+        if pidfd.is_some() {
+            check_start_time(container_pid, start_time)?;
+        }
+
+        if pidfd.is_some() && kernel_version() >= Some(PIDFD_SETNS_VERSION) {
+            return Ok(Ns {
+                pidfd,
+                files: Vec::new(),
+                join_user,
+                user: None,
+                mnt: None,
+            });
+        }
+
         let ns_base_path = format!("/proc/{}/ns", container_pid);
+        let files = DEFAULT_KINDS
+            .iter()
+            .map(|kind| Ok((*kind, File::open(format!("{}/{}", &ns_base_path, kind))?)))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        let user = match join_user {
+            true => Some(File::open(format!("{}/user", &ns_base_path))?),
+            false => None,
+        };
+        let mnt = File::open(format!("{}/mnt", &ns_base_path))?;
+        match pidfd {
+            // 開いている間にプロセスが終了していなければ、同じプロセスのnamespace
+            Some(ref pidfd) => {
+                if pidfd_send_signal(pidfd, 0) == Err(Errno::ESRCH) {
+                    Err(container::Error::ProcessReplaced(container_pid))?
+                }
+            }
+            None => check_start_time(container_pid, start_time)?,
+        }
 
         Ok(Ns {
-            net: File::open(format!("{}/net", &ns_base_path))?,
-            cgroup: File::open(format!("{}/cgroup", &ns_base_path))?,
-            ipc: File::open(format!("{}/ipc", &ns_base_path))?,
-            pid: File::open(format!("{}/pid", &ns_base_path))?,
-            user: File::open(format!("{}/user", &ns_base_path))?,
-            mnt: File::open(format!("{}/mnt", &ns_base_path))?,
-            uts: File::open(format!("{}/uts", &ns_base_path))?,
+            pidfd,
+            files,
+            join_user,
+            user,
+            mnt: Some(mnt),
         })
     }
 
-    pub fn setns_net(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.net.as_raw_fd(), CloneFlags::empty())?;
-        Ok(())
+    /// デバック対象のuser namespaceに入るかどうか
+    ///
+    /// 入らない場合は、呼び出し側で新しいuser namespaceを作成する
+    pub fn joins_user(&self) -> bool {
+        self.join_user
     }
 
-    pub fn setns_cgroup(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.cgroup.as_raw_fd(), CloneFlags::empty())?;
+    /// 指定された種類のnamespaceに入る
+    ///
+    /// デバック対象のuser namespaceがホストと異なる場合は、他のnamespaceより先にそのuser namespaceに入り、
+    /// デバック対象から見たrootになる。
+    /// `/proc/<PID>/ns/*`を開いている場合は、`DEFAULT_KINDS`の順で入る
+    pub fn setns(&self, kinds: &[Kind]) -> Result<(), Box<dyn std::error::Error>> {
+        match (&self.pidfd, self.files.is_empty()) {
+            (Some(pidfd), true) => {
+                let mut flags = kinds
+                    .iter()
+                    .fold(CloneFlags::empty(), |flags, kind| flags | kind.clone_flag());
+                if self.join_user {
+                    flags |= CloneFlags::CLONE_NEWUSER;
+                }
+                // カーネルがuser namespaceを先に切り替え、その権限で残りのnamespaceに入る
+                if !flags.is_empty() {
+                    setns(pidfd.as_raw_fd(), flags)?;
+                }
+            }
+            _ => {
+                if let Some(ref user) = self.user {
+                    setns(user.as_raw_fd(), CloneFlags::CLONE_NEWUSER)?;
+                }
+                for (_, file) in self.files.iter().filter(|(kind, _)| kinds.contains(kind)) {
+                    setns(file.as_raw_fd(), CloneFlags::empty())?;
+                }
+            }
+        }
+        if self.join_user {
+            become_root()?;
+        }
+
         Ok(())
     }
 
-    pub fn setns_ipc(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.ipc.as_raw_fd(), CloneFlags::empty())?;
+    /// デバック対象のmount namespaceに入る
+    ///
+    /// ルートディレクトリもデバック対象のものになる。`setns`の後に呼ぶ
+    pub fn setns_mnt(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // pidfdでsetnsできない場合は`/proc/<PID>/ns/mnt`を開いている
+        let fd = match (&self.mnt, &self.pidfd) {
+            (Some(mnt), _) => mnt.as_raw_fd(),
+            (None, Some(pidfd)) => pidfd.as_raw_fd(),
+            (None, None) => Err(Error::MountNamespaceNotOpened)?,
+        };
+        setns(fd, CloneFlags::CLONE_NEWNS)?;
+
         Ok(())
     }
+}
 
-    pub fn setns_pid(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.pid.as_raw_fd(), CloneFlags::empty())?;
-        Ok(())
+/// 入ったuser namespaceのrootになる
+///
+/// ホストのuidのままではuser namespace内でマップされておらず、
+/// ファイルの所有者が正しく見えず、デバック対象のプロセスにシグナルも送れない
+fn become_root() -> Result<(), Box<dyn std::error::Error>> {
+    // rootless Dockerなど、setgroupsが禁止されているuser namespaceでは補助グループはそのまま
+    match setgroups(&[]) {
+        Ok(()) | Err(Errno::EPERM) => {}
+        Err(e) => Err(e)?,
     }
+    let root_gid = Gid::from_raw(0);
+    let root_uid = Uid::from_raw(0);
+    setresgid(root_gid, root_gid, root_gid)?;
+    setresuid(root_uid, root_uid, root_uid)?;
 
-    pub fn setns_user(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.user.as_raw_fd(), CloneFlags::empty())?;
-        Ok(())
+    Ok(())
+}
+
+/// プロセス`pid`の`name`namespaceが、このプロセスのものと同じかどうか
+///
+/// namespaceのファイルシステム(nsfs)のデバイス番号とinode番号の組で比べる
+pub(crate) fn is_same_namespace(pid: u32, name: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    Ok(identity(None, name)? == identity(Some(pid), name)?)
+}
+
+/// プロセスの`name`namespaceを識別する、nsfsのデバイス番号とinode番号の組。`pid`が`None`の場合はこのプロセス
+///
+/// 組が同じプロセスは、同じnamespaceに属する
+pub fn identity(pid: Option<u32>, name: &str) -> Result<(u64, u64), std::io::Error> {
+    let pid = pid.map_or("self".to_string(), |pid| pid.to_string());
+    let metadata = fs::metadata(format!("/proc/{}/ns/{}", pid, name))?;
+
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// プロセスの`name`namespaceのinode番号。`pid`が`None`の場合はこのプロセス
+///
+/// 表示用。同じnamespaceかどうかは`identity`で比べる
+pub fn inode(pid: Option<u32>, name: &str) -> Result<u64, std::io::Error> {
+    Ok(identity(pid, name)?.1)
+}
+
+/// user namespaceのuid、gidのマッピングの1行
+#[derive(Debug, PartialEq, Serialize)]
+pub struct IdMap {
+    /// user namespace内のID
+    pub inside: u32,
+    /// 親のuser namespaceでのID
+    pub outside: u32,
+    pub count: u32,
+}
+
+/// プロセスの`/proc/<PID>/uid_map`または`gid_map`を読み込む
+pub fn id_map(pid: u32, file: &str) -> Result<Vec<IdMap>, Box<dyn std::error::Error>> {
+    let map = fs::read_to_string(format!("/proc/{}/{}", pid, file))?;
+
+    Ok(parse_id_map(&map).ok_or_else(|| Error::InvalidIdMap(file.to_string()))?)
+}
+
+fn parse_id_map(map: &str) -> Option<Vec<IdMap>> {
+    map.lines()
+        .map(|line| {
+            let mut fields = line.split_whitespace().map(|field| field.parse().ok());
+            Some(IdMap {
+                inside: fields.next()??,
+                outside: fields.next()??,
+                count: fields.next()??,
+            })
+        })
+        .collect()
+}
+
+fn pidfd_open(pid: u32) -> Result<File, Errno> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(Errno::last());
     }
 
-    pub fn setns_mnt(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.mnt.as_raw_fd(), CloneFlags::empty())?;
-        Ok(())
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+fn pidfd_send_signal(pidfd: &File, signal: libc::c_int) -> Result<(), Errno> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Errno::last());
     }
 
-    pub fn setns_uts(&self) -> Result<(), Box<dyn std::error::Error>> {
-        setns(self.uts.as_raw_fd(), CloneFlags::empty())?;
-        Ok(())
+    Ok(())
+}
+
+fn check_start_time(pid: u32, expected: u64) -> Result<(), Box<dyn std::error::Error>> {
+    match container::start_time(pid) {
+        Ok(start_time) if start_time == expected => Ok(()),
+        _ => Err(container::Error::ProcessReplaced(pid))?,
+    }
+}
+
+/// 実行中のカーネルの(メジャー, マイナー)バージョン
+fn kernel_version() -> Option<(u32, u32)> {
+    parse_kernel_version(uname().release())
+}
+
+/// `5.15.0-91-generic`のようなリリース文字列からバージョンを取り出す
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|number| number.parse().ok());
+
+    Some((numbers.next()??, numbers.next()??))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selection_apply() {
+        let only_net = Selection::parse(&["net".to_string()], &[]).unwrap();
+        assert_eq!(only_net.apply(&DEFAULT_KINDS), vec![Kind::Net]);
+
+        let no_pid = Selection::parse(&[], &["pid".to_string()]).unwrap();
+        assert_eq!(
+            no_pid.apply(&DEFAULT_KINDS),
+            vec![Kind::Net, Kind::Cgroup, Kind::Ipc, Kind::Uts]
+        );
+        assert_eq!(no_pid.apply(&[Kind::Pid, Kind::Net]), vec![Kind::Net]);
+
+        assert!(Selection::default().is_empty());
+        assert!(matches!(
+            Selection::parse(&["time".to_string()], &[]),
+            Err(Error::UnknownNamespace(kind)) if kind == "time"
+        ));
+        assert!(matches!(
+            Selection::parse(&[], &["mnt".to_string()]),
+            Err(Error::MountNamespaceNotSelectable)
+        ));
+        assert!(matches!(
+            Selection::parse(&["user".to_string()], &[]),
+            Err(Error::UserNamespaceNotSelectable)
+        ));
+    }
+
+    #[test]
+    fn test_is_same_namespace() {
+        assert!(is_same_namespace(std::process::id(), "user").unwrap());
+        assert!(is_same_namespace(u32::MAX, "user").is_err());
+    }
+
+    #[test]
+    fn test_parse_id_map() {
+        let map = "         0     100000      65536\n     65536       1000          1\n";
+        assert_eq!(
+            parse_id_map(map),
+            Some(vec![
+                IdMap {
+                    inside: 0,
+                    outside: 100000,
+                    count: 65536
+                },
+                IdMap {
+                    inside: 65536,
+                    outside: 1000,
+                    count: 1
+                },
+            ])
+        );
+        assert_eq!(parse_id_map("0 0\n"), None);
+    }
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("5.15.0-91-generic"), Some((5, 15)));
+        assert_eq!(parse_kernel_version("6.1.55+"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("4.19.0"), Some((4, 19)));
+        assert!(parse_kernel_version("4.19.0") < Some(PIDFD_SETNS_VERSION));
+        assert_eq!(parse_kernel_version("unknown"), None);
     }
 }
@@ -1,9 +1,14 @@
-use crate::command::{self, Cmd, Delete, Error, Exec, File, Init, Launch, List, RootFSOption};
+use crate::command::{
+    self, launch_error, watch_error, Cmd, Delete, Error, Exec, File, Init, Launch, List, Ns,
+    RootFSOption, Watch,
+};
+use crate::docker_client::DockerClient;
 use crate::{
-    container, image, image_downloader, image_downloader_lxd, setting, setting_yaml, user,
+    container, container_cri, container_lxd, container_machined, image, image_downloader,
+    image_downloader_lxd, namespace, picker, setting, setting_yaml, user, utils,
 };
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, PossibleValue, Subcommand};
 use std::path::PathBuf;
 
 pub fn initialize_delete(delete: DeleteArgs) -> Result<Delete, Box<dyn std::error::Error>> {
@@ -24,6 +29,11 @@ pub fn initialize_exec(
     Ok(Exec::new(
         exec.name,
         Cmd::new(Box::new(exec.cmd.into_iter())),
+        namespace::Selection::parse(&exec.ns, &exec.no_ns)?,
+        exec.join_cgroup.map(|cgroup| cgroup.parse()).transpose()?,
+        exec.match_security,
+        exec.tty,
+        exec.interactive,
         setting_yaml_reader_writer,
     ))
 }
@@ -57,7 +67,7 @@ where
 }
 
 pub fn initialize_launch(
-    launch: LaunchArgs,
+    mut launch: LaunchArgs,
 ) -> Result<
     Launch<impl image_downloader::Downloader, impl setting::Reader + setting::Writer>,
     Box<dyn std::error::Error>,
@@ -69,26 +79,160 @@ pub fn initialize_launch(
         launch.opt_rootfs_lxd.as_ref().map(|r| r.as_str()),
     )?;
 
-    let container = container::Container::new(&launch.container_id_or_name)?;
+    // デバック対象をオプションで指定した場合、位置引数は[NAME] [CMD]の順になる
+    let target_selected_by_option = launch.pod.is_some()
+        || launch.pid.is_some()
+        || launch.cgroup.is_some()
+        || launch.target_lxd.is_some()
+        || launch.machine.is_some()
+        || !launch.label.is_empty()
+        || launch.compose_project.is_some();
+    let mut positionals = launch
+        .container_id_or_name
+        .take()
+        .into_iter()
+        .chain(launch.name.take())
+        .chain(launch.cmd.drain(..));
+    let container_id_or_name = match target_selected_by_option {
+        true => None,
+        false => positionals.next(),
+    };
+    let name = positionals.next();
+    let cmd: Vec<String> = positionals.collect();
+
+    // コンテナの選択から解決まで、Dockerのクライアントは1つを使う
+    let mut docker = None;
+
+    // デバック対象の指定がない場合、起動中のDockerコンテナから選択する
+    let picked = match (target_selected_by_option, &container_id_or_name) {
+        (false, None) if launch.runtime == "docker" => {
+            Some(picker::pick_container(docker_client(&mut docker)?)?)
+        }
+        _ => None,
+    };
 
     let user = user::User::new()?;
-    let dcontainer_base = format!("{}/{}", user.containers(), launch.name);
+    let name = match (name, &picked) {
+        (Some(name), _) => name,
+        (None, Some(picked)) => utils::generate_name(&user, picked.name()),
+        (None, None) => Err(launch_error::Error::NameNotFound)?,
+    };
+
+    let mut container = if let Some(pod) = &launch.pod {
+        container_cri::new(pod, launch.container.as_deref())?
+    } else if let Some(pid) = launch.pid {
+        container::Container::from_process(pid)?
+    } else if let Some(cgroup) = &launch.cgroup {
+        container::Container::from_cgroup(cgroup)?
+    } else if let Some(lxd_container) = &launch.target_lxd {
+        container_lxd::new(lxd_container)?
+    } else if let Some(machine) = &launch.machine {
+        container_machined::new(machine)?
+    } else if !launch.label.is_empty() || launch.compose_project.is_some() {
+        let labels = std::mem::take(&mut launch.label);
+        let selector = match &launch.compose_project {
+            Some(project) => container::Selector::compose(
+                project,
+                launch.compose_service.as_deref(),
+                labels,
+                launch.replica,
+            ),
+            None => container::Selector::new(labels, launch.replica),
+        };
+        container::Container::from_selector(docker_client(&mut docker)?, &selector)?
+    } else {
+        let runtime = match launch.runtime.parse()? {
+            container::Runtime::Containerd(_) => {
+                container::Runtime::Containerd(launch.containerd_namespace.clone())
+            }
+            runtime => runtime,
+        };
+        let container_id_or_name = picked
+            .map(|picked| picked.id().to_string())
+            .or(container_id_or_name)
+            .ok_or(launch_error::Error::ContainerIdOrNameNotFound)?;
+        match runtime {
+            container::Runtime::Docker => {
+                container::Container::new(docker_client(&mut docker)?, &container_id_or_name)?
+            }
+            runtime => container::Container::with_runtime(&runtime, &container_id_or_name)?,
+        }
+    };
+    if launch.proc_root {
+        container.use_proc_root();
+    }
+
+    let namespaces =
+        namespace::Selection::parse(&launch.ns, &launch.no_ns)?.apply(&namespace::DEFAULT_KINDS);
+
+    let dcontainer_base = format!("{}/{}", user.containers(), name);
     let setting_file_path = PathBuf::from(format!("{}/setting.yaml", &dcontainer_base));
     let setting_yaml_reader_writer = setting_yaml::YamlReaderWriter::new(&setting_file_path);
 
     Launch::new(
         container,
         rootfs,
-        String::from(launch.name),
-        Cmd::new(Box::new(launch.cmd.into_iter())),
+        name,
+        Cmd::new(Box::new(cmd.into_iter())),
+        namespaces,
+        launch.mount_ns,
+        launch
+            .join_cgroup
+            .map(|cgroup| cgroup.parse())
+            .transpose()?,
+        launch.match_security,
+        launch.tty,
+        launch.interactive,
         setting_yaml_reader_writer,
     )
 }
 
+/// Dockerのクライアントを初めて使う時に作成し、以降は同じものを返す
+fn docker_client(
+    docker: &mut Option<DockerClient>,
+) -> Result<&mut DockerClient, Box<dyn std::error::Error>> {
+    let client = match docker.take() {
+        Some(client) => client,
+        None => DockerClient::from_env()?,
+    };
+
+    Ok(docker.insert(client))
+}
+
+pub fn initialize_watch(
+    watch: WatchArgs,
+) -> Result<Watch<impl image_downloader::Downloader>, Box<dyn std::error::Error>> {
+    let rootfs = check_rootfs(
+        watch.opt_rootfs.as_deref(),
+        watch.opt_rootfs_image.as_deref(),
+        watch.opt_rootfs_docker.as_deref(),
+        watch.opt_rootfs_lxd.as_deref(),
+    )?;
+    if let RootFSOption::None = rootfs {
+        Err(watch_error::Error::RootfsNotFound)?
+    }
+
+    let selector = match &watch.compose_project {
+        Some(project) => container::Selector::compose(
+            project,
+            watch.compose_service.as_deref(),
+            watch.label,
+            None,
+        ),
+        None => container::Selector::new(watch.label, None),
+    };
+
+    Ok(Watch::new(selector, rootfs, watch.delete))
+}
+
 pub fn initialize_list() -> Result<List, Box<dyn std::error::Error>> {
     Ok(List::new()?)
 }
 
+pub fn initialize_ns(ns: NsArgs) -> Result<Ns, Box<dyn std::error::Error>> {
+    Ok(Ns::new(ns.name, ns.json))
+}
+
 pub fn check_rootfs(
     opt_rootfs: Option<&str>,
     opt_rootfs_image: Option<&str>,
@@ -175,14 +319,45 @@ NAME is the name of the debug container. If it is not specified, it will be gene
 If CMD is not specified, the default shell is used.";
 const DELETE_ABOUT: &str = "Remove the debug container";
 const LIST_ABOUT: &str = "List debug containers";
+const WATCH_ABOUT: &str =
+    "Watch Docker events and manage debug containers of the matching containers
+When a container starts, a debug container is created for it with the given rootfs.
+When it dies or is removed, its debug containers are unmounted, and deleted with --delete.";
+const NS_ABOUT: &str = "Show the namespaces of the host, the target and the debug session
+For each namespace type, the inodes are compared to tell which of them are shared.
+The uid/gid maps of the user namespaces are shown as well.";
 const FILE_ABOUT: &str = "File operations in the debug container";
 const FILE_PULL_ABOUT: &str = "Download the specified file of the debug container.";
 const FILE_PUSH_ABOUT: &str =
     "Uploading the specified file of the host to the specified PATH of the debug container";
 
+const NAMESPACES: &[&str] = &["net", "cgroup", "ipc", "pid", "uts"];
+/// `--ns`、`--no-ns`では選べず、専用の方法で入るnamespace
+const DEDICATED_NAMESPACES: &[&str] = &["mnt", "user"];
+const CGROUP_PLACEMENTS: &[&str] = &["target", "child"];
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
 
+/// `--ns`、`--no-ns`の値
+///
+/// `DEDICATED_NAMESPACES`はヘルプに表示せずに受け付け、`validate_namespace`で入る方法を示して拒否する
+fn namespace_values() -> Vec<PossibleValue<'static>> {
+    NAMESPACES
+        .iter()
+        .map(|kind| PossibleValue::new(kind))
+        .chain(
+            DEDICATED_NAMESPACES
+                .iter()
+                .map(|kind| PossibleValue::new(kind).hide(true)),
+        )
+        .collect()
+}
+
+fn validate_namespace(kind: &str) -> Result<namespace::Kind, namespace::Error> {
+    kind.parse()
+}
+
 // basic arguments
 #[derive(Parser)]
 #[clap(about = ABOUT_THIS_APP, version = VERSION, author = AUTHOR)]
@@ -194,11 +369,14 @@ pub struct Cli {
     // - init
     // - launch
     // - list
+    // - ns
+    // - watch
     #[clap(subcommand)]
     pub action: Action,
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Action {
     // delete
     #[clap(name = "delete", about = DELETE_ABOUT)]
@@ -218,6 +396,12 @@ pub enum Action {
     // list
     #[clap(name = "list", about = LIST_ABOUT)]
     List,
+    // ns
+    #[clap(name = "ns", about = NS_ABOUT)]
+    Ns(NsArgs),
+    // watch
+    #[clap(name = "watch", about = WATCH_ABOUT)]
+    Watch(WatchArgs),
 }
 
 #[derive(Args)]
@@ -226,12 +410,51 @@ pub struct DeleteArgs {
     pub name: String,
 }
 
+#[derive(Args)]
+pub struct NsArgs {
+    /// Name of a debug container, or ID or name of a container
+    #[clap()]
+    pub name: String,
+    /// Print as JSON
+    #[clap(long = "--json")]
+    pub json: bool,
+}
+
 #[derive(Args)]
 pub struct ExecArgs {
     #[clap()]
     pub name: String,
+    /// Command and its arguments. Put them after -- when they start with -
     #[clap()]
-    pub cmd: Option<String>,
+    pub cmd: Vec<String>,
+    /// Namespaces to join instead of the ones chosen at launch, comma separated
+    #[clap(
+        long = "--ns",
+        use_value_delimiter = true,
+        possible_values = namespace_values(),
+        validator = validate_namespace
+    )]
+    pub ns: Vec<String>,
+    /// Namespaces not to join, comma separated
+    #[clap(
+        long = "--no-ns",
+        use_value_delimiter = true,
+        possible_values = namespace_values(),
+        validator = validate_namespace
+    )]
+    pub no_ns: Vec<String>,
+    /// Place the session in the target's cgroup, or in an injesh cgroup beneath it
+    #[clap(long = "--join-cgroup", possible_values = CGROUP_PLACEMENTS)]
+    pub join_cgroup: Option<String>,
+    /// Run CMD with the target's capabilities, no_new_privs and seccomp filters
+    #[clap(long = "--match-security")]
+    pub match_security: bool,
+    /// Allocate a pseudo-TTY
+    #[clap(short = 't', long = "--tty")]
+    pub tty: bool,
+    /// Keep STDIN open
+    #[clap(short = 'i', long = "--interactive")]
+    pub interactive: bool,
 }
 
 #[derive(Subcommand)]
@@ -254,9 +477,10 @@ pub struct FileArgs {
 }
 
 #[derive(Args)]
+#[clap(group = ArgGroup::new("selector").multiple(true).args(&["label", "compose-project"]))]
 pub struct LaunchArgs {
     #[clap()]
-    pub container_id_or_name: String,
+    pub container_id_or_name: Option<String>,
     #[clap(long = "--rootfs")]
     pub opt_rootfs: Option<String>,
     #[clap(long = "--rootfs-image")]
@@ -265,12 +489,119 @@ pub struct LaunchArgs {
     pub opt_rootfs_docker: Option<String>,
     #[clap(long = "--rootfs-lxd")]
     pub opt_rootfs_lxd: Option<String>,
+    #[clap(
+        long = "--runtime",
+        default_value = "docker",
+        possible_values = &["docker", "podman", "containerd", "cri"]
+    )]
+    pub runtime: String,
+    #[clap(long = "--containerd-namespace", default_value = "default")]
+    pub containerd_namespace: String,
+    /// Kubernetes pod to debug as <NAMESPACE>/<POD>, resolved through the CRI socket
+    #[clap(long = "--pod")]
+    pub pod: Option<String>,
+    /// Container in the pod given by --pod
+    #[clap(long = "--container", requires = "pod")]
+    pub container: Option<String>,
+    /// Process to debug, without any container runtime
+    #[clap(long = "--pid", conflicts_with = "pod")]
+    pub pid: Option<u32>,
+    /// cgroup v2 directory whose main (oldest) process is debugged
+    #[clap(long = "--cgroup", conflicts_with_all = &["pod", "pid"])]
+    pub cgroup: Option<String>,
+    /// LXD system container to debug, resolved through the LXD API socket
+    #[clap(long = "--target-lxd", conflicts_with_all = &["pod", "pid", "cgroup"])]
+    pub target_lxd: Option<String>,
+    /// Machine registered with systemd-machined, e.g. a systemd-nspawn container
+    #[clap(
+        long = "--machine",
+        conflicts_with_all = &["pod", "pid", "cgroup", "target-lxd"]
+    )]
+    pub machine: Option<String>,
+    /// Select the running Docker container by a label as key=value, may be repeated
+    #[clap(
+        long = "--label",
+        conflicts_with_all = &["pod", "pid", "cgroup", "target-lxd", "machine"]
+    )]
+    pub label: Vec<String>,
+    /// Select a container of the compose project
+    #[clap(
+        long = "--compose-project",
+        conflicts_with_all = &["pod", "pid", "cgroup", "target-lxd", "machine"]
+    )]
+    pub compose_project: Option<String>,
+    /// Service of the compose project given by --compose-project
+    #[clap(long = "--compose-service", requires = "compose-project")]
+    pub compose_service: Option<String>,
+    /// Which of several containers matching --label or --compose-project to select, starting from 1
+    #[clap(long = "--replica", requires = "selector")]
+    pub replica: Option<usize>,
+    /// Use /proc/<PID>/root instead of the runtime's MergedDir as the target's filesystem
+    #[clap(long = "--proc-root")]
+    pub proc_root: bool,
+    /// Namespaces to join, comma separated. All of them by default
+    #[clap(
+        long = "--ns",
+        use_value_delimiter = true,
+        possible_values = namespace_values(),
+        validator = validate_namespace
+    )]
+    pub ns: Vec<String>,
+    /// Namespaces not to join, comma separated
+    #[clap(
+        long = "--no-ns",
+        use_value_delimiter = true,
+        possible_values = namespace_values(),
+        validator = validate_namespace
+    )]
+    pub no_ns: Vec<String>,
+    /// Join the target's mount namespace and mount the debug container on an empty directory (/mnt, /media, /srv, /opt or /home) instead of chrooting
+    #[clap(long = "--mount-ns")]
+    pub mount_ns: bool,
+    /// Place the session in the target's cgroup, or in an injesh cgroup beneath it.
+    /// Its memory and CPU usage is then accounted and limited as the target's
+    #[clap(long = "--join-cgroup", possible_values = CGROUP_PLACEMENTS)]
+    pub join_cgroup: Option<String>,
+    /// Run CMD with the target's capabilities, no_new_privs and seccomp filters,
+    /// to reproduce permission errors as the target sees them
+    #[clap(long = "--match-security")]
+    pub match_security: bool,
+    /// Allocate a pseudo-TTY
+    #[clap(short = 't', long = "--tty")]
+    pub tty: bool,
+    /// Keep STDIN open
+    #[clap(short = 'i', long = "--interactive")]
+    pub interactive: bool,
     #[clap()]
-    pub name: String,
+    pub name: Option<String>,
     #[clap()]
     pub cmd: Vec<String>,
 }
 
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Watch the Docker containers with a label as key=value, may be repeated
+    #[clap(long = "--label", required_unless_present = "compose-project")]
+    pub label: Vec<String>,
+    /// Watch the containers of the compose project
+    #[clap(long = "--compose-project")]
+    pub compose_project: Option<String>,
+    /// Service of the compose project given by --compose-project
+    #[clap(long = "--compose-service", requires = "compose-project")]
+    pub compose_service: Option<String>,
+    #[clap(long = "--rootfs")]
+    pub opt_rootfs: Option<String>,
+    #[clap(long = "--rootfs-image")]
+    pub opt_rootfs_image: Option<String>,
+    #[clap(long = "--rootfs-docker")]
+    pub opt_rootfs_docker: Option<String>,
+    #[clap(long = "--rootfs-lxd")]
+    pub opt_rootfs_lxd: Option<String>,
+    /// Delete the debug containers when their target dies, instead of only unmounting them
+    #[clap(long = "--delete")]
+    pub delete: bool,
+}
+
 #[derive(Args)]
 pub struct PullArgs {
     #[clap()]
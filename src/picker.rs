@@ -0,0 +1,164 @@
+//! Terminal picker of the target container.
+//!
+//! `launch` opens it when no target is given,
+//! listing the running Docker containers like `docker ps` does.
+
+use crate::container::{self, DockerContainerList, DockerPort};
+use crate::docker_client::DockerClient;
+use dialoguer::{console, theme::ColorfulTheme, FuzzySelect};
+use std::{error, fmt};
+
+#[derive(Debug)]
+pub enum Error {
+    NotATerminal,
+    NoRunningContainer,
+    Canceled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::NotATerminal => write!(
+                f,
+                "picker: not a terminal, give the container ID or name instead"
+            ),
+            Error::NoRunningContainer => write!(f, "picker: no running container"),
+            Error::Canceled => write!(f, "picker: canceled"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// The container chosen in the picker
+#[derive(Debug, PartialEq)]
+pub struct Picked {
+    id: String,
+    name: String,
+}
+
+impl Picked {
+    /// Full ID of the container
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Lets the user choose one of the running containers, filtered by fuzzy matching.
+pub fn pick_container(client: &mut DockerClient) -> Result<Picked, Box<dyn std::error::Error>> {
+    if !console::user_attended() || !console::user_attended_stderr() {
+        Err(Error::NotATerminal)?
+    }
+    let mut containers = container::list_running_containers(client)?;
+    if containers.is_empty() {
+        Err(Error::NoRunningContainer)?
+    }
+
+    let (header, rows) = rows(&containers);
+    eprintln!("  {}", header);
+    let index = FuzzySelect::with_theme(&ColorfulTheme::default())
+        .items(&rows)
+        .default(0)
+        .interact_opt()?
+        .ok_or(Error::Canceled)?;
+
+    let container = containers.swap_remove(index);
+    Ok(Picked {
+        name: name(&container).to_string(),
+        id: container.Id,
+    })
+}
+
+/// Formats the header and one row per container, in aligned columns:
+/// name, image, status and ports.
+///
+/// The status is the text of `docker ps`, which tells the uptime and the health, e.g. `Up 2 hours (healthy)`.
+fn rows(containers: &[DockerContainerList]) -> (String, Vec<String>) {
+    let mut table = vec![[
+        "NAME".to_string(),
+        "IMAGE".to_string(),
+        "STATUS".to_string(),
+        "PORTS".to_string(),
+    ]];
+    table.extend(containers.iter().map(|container| {
+        [
+            name(container).to_string(),
+            container.Image.clone(),
+            container.Status.clone(),
+            ports(&container.Ports),
+        ]
+    }));
+
+    let mut widths = [0; 4];
+    for row in &table {
+        for (width, column) in widths.iter_mut().zip(row) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+    let mut lines = table.iter().map(|row| {
+        let columns: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(column, width)| format!("{:width$}", column, width = width))
+            .collect();
+        columns.join("  ").trim_end().to_string()
+    });
+
+    let header = lines.next().unwrap_or_default();
+    (header, lines.collect())
+}
+
+fn name(container: &DockerContainerList) -> &str {
+    // names are listed with a leading slash, e.g. `/web`
+    match container.Names.first() {
+        Some(name) => name.trim_start_matches('/'),
+        None => container::short_id(&container.Id),
+    }
+}
+
+/// Formats published ports as `docker ps` does, e.g. `0.0.0.0:8080->80/tcp, 443/tcp`.
+fn ports(ports: &[DockerPort]) -> String {
+    ports
+        .iter()
+        .map(|port| match (&port.IP, port.PublicPort) {
+            (Some(ip), Some(public_port)) => {
+                format!("{}:{}->{}/{}", ip, public_port, port.PrivatePort, port.Type)
+            }
+            _ => format!("{}/{}", port.PrivatePort, port.Type),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows() {
+        let containers: Vec<DockerContainerList> = serde_json::from_str(
+            r#"[
+                {"Id":"8f3a1c2b9d7e0000","Names":["/web"],"Image":"nginx:1.21","State":"running","Status":"Up 2 hours",
+                 "Ports":[{"IP":"0.0.0.0","PrivatePort":80,"PublicPort":8080,"Type":"tcp"},{"PrivatePort":443,"Type":"tcp"}]},
+                {"Id":"1b2c3d4e5f600000","Names":["/checkout-api-1"],"Image":"api","State":"running","Status":"Up 5 minutes (healthy)","Ports":[]}
+            ]"#,
+        )
+        .unwrap();
+
+        let (header, rows) = rows(&containers);
+        assert_eq!(
+            header,
+            "NAME            IMAGE       STATUS                  PORTS"
+        );
+        assert_eq!(
+            rows,
+            vec![
+                "web             nginx:1.21  Up 2 hours              0.0.0.0:8080->80/tcp, 443/tcp",
+                "checkout-api-1  api         Up 5 minutes (healthy)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use nix::errno::Errno;
+use nix::sys::ptrace;
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::Pid;
+use std::fs;
+use std::{error, fmt};
+
+// <linux/ptrace.h>
+const PTRACE_SECCOMP_GET_FILTER: libc::c_uint = 0x420c;
+// <linux/securebits.h>
+const SECBIT_NOROOT: libc::c_ulong = 1 << 0;
+const SECBIT_NOROOT_LOCKED: libc::c_ulong = 1 << 1;
+// <linux/capability.h>
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+const SECCOMP_MODE_STRICT: u32 = 1;
+const SECCOMP_MODE_FILTER: u32 = 2;
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidStatus(u32),
+    StrictSeccomp(u32),
+    SeccompFilterUnavailable(u32, Errno),
+    ApplyFailed(&'static str, Errno),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidStatus(pid) => write!(f, "security: invalid /proc/{}/status", pid),
+            Error::StrictSeccomp(pid) => write!(
+                f,
+                "security: process {} runs in seccomp strict mode, which cannot run a shell",
+                pid
+            ),
+            Error::SeccompFilterUnavailable(pid, why) => write!(
+                f,
+                "security: failed to read the seccomp filters of process {} (CAP_SYS_ADMIN and ptrace are required): {}",
+                pid, why
+            ),
+            Error::ApplyFailed(step, why) => write!(f, "security: failed to {}: {}", step, why),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// `/proc/<PID>/status`から読み取る項目
+#[derive(Debug, PartialEq)]
+struct Status {
+    cap_eff: u64,
+    cap_bnd: u64,
+    no_new_privs: bool,
+    seccomp: u32,
+}
+
+/// デバック対象プロセスのセキュリティコンテキスト
+///
+/// ケーパビリティ、no_new_privs、seccompフィルタを子プロセスに同じように設定し、
+/// デバック対象のアプリケーションと同じ権限でコマンドを実行する
+pub struct Context {
+    status: Status,
+    /// `/proc/sys/kernel/cap_last_cap`。chroot後は読めないことがあるため事前に読む
+    last_cap: u32,
+    /// デバック対象に設定されたseccompフィルタ。古いものから順に並ぶ
+    filters: Vec<Vec<libc::sock_filter>>,
+}
+
+impl Context {
+    /// デバック対象のプロセス`pid`のセキュリティコンテキストを読み込む
+    ///
+    /// seccompフィルタはptraceで取得するため、デバック対象は一瞬停止する
+    pub fn read(pid: u32) -> Result<Context, Box<dyn std::error::Error>> {
+        let status = fs::read_to_string(format!("/proc/{}/status", pid))?;
+        let status = parse_status(&status).ok_or(Error::InvalidStatus(pid))?;
+        let last_cap = fs::read_to_string("/proc/sys/kernel/cap_last_cap")?
+            .trim()
+            .parse()?;
+
+        let filters = match status.seccomp {
+            SECCOMP_MODE_STRICT => Err(Error::StrictSeccomp(pid))?,
+            SECCOMP_MODE_FILTER => {
+                seccomp_filters(pid).map_err(|why| Error::SeccompFilterUnavailable(pid, why))?
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(Context {
+            status,
+            last_cap,
+            filters,
+        })
+    }
+
+    /// このプロセスに適用する。execの直前に呼ぶ
+    ///
+    /// uidが0でもexec時にケーパビリティを得ないようにし、
+    /// デバック対象の実効ケーパビリティをambientとして引き継ぐ
+    pub fn apply(&self) -> Result<(), Error> {
+        for cap in 0..=self.last_cap {
+            if self.status.cap_bnd & (1 << cap) == 0 {
+                prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0)
+                    .map_err(|why| Error::ApplyFailed("drop the bounding set", why))?;
+            }
+        }
+        prctl(
+            libc::PR_SET_SECUREBITS,
+            SECBIT_NOROOT | SECBIT_NOROOT_LOCKED,
+            0,
+        )
+        .map_err(|why| Error::ApplyFailed("set securebits", why))?;
+        if self.status.no_new_privs {
+            prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0)
+                .map_err(|why| Error::ApplyFailed("set no_new_privs", why))?;
+        }
+        // no_new_privsが無い場合、フィルタの設定にはCAP_SYS_ADMINが必要なため、ケーパビリティを減らす前に設定する
+        for filter in &self.filters {
+            set_seccomp_filter(filter)
+                .map_err(|why| Error::ApplyFailed("set the seccomp filter", why))?;
+        }
+
+        capset(self.status.cap_eff).map_err(|why| Error::ApplyFailed("set capabilities", why))?;
+        for cap in 0..=self.last_cap {
+            if self.status.cap_eff & (1 << cap) != 0 {
+                prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                    cap as libc::c_ulong,
+                )
+                .map_err(|why| Error::ApplyFailed("raise ambient capabilities", why))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_status(status: &str) -> Option<Status> {
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .map(str::trim)
+    };
+
+    Some(Status {
+        cap_eff: u64::from_str_radix(field("CapEff")?, 16).ok()?,
+        cap_bnd: u64::from_str_radix(field("CapBnd")?, 16).ok()?,
+        no_new_privs: field("NoNewPrivs")? == "1",
+        seccomp: field("Seccomp")?.parse().ok()?,
+    })
+}
+
+/// ptraceでデバック対象を停止し、seccompフィルタを取得する
+fn seccomp_filters(pid: u32) -> Result<Vec<Vec<libc::sock_filter>>, Errno> {
+    let target = Pid::from_raw(pid as libc::pid_t);
+    ptrace::seize(target, ptrace::Options::empty())?;
+
+    let filters = ptrace::interrupt(target)
+        .and_then(|_| waitpid(target, Some(WaitPidFlag::__WALL)))
+        .and_then(|_| read_seccomp_filters(target));
+    // 停止したデバック対象は切り離すと再開する
+    let _ = ptrace::detach(target, None);
+
+    filters
+}
+
+fn read_seccomp_filters(target: Pid) -> Result<Vec<Vec<libc::sock_filter>>, Errno> {
+    let mut filters = Vec::new();
+    // 0が最後に設定されたフィルタ。範囲外になるとENOENT
+    for index in 0.. {
+        let len = unsafe {
+            libc::ptrace(
+                PTRACE_SECCOMP_GET_FILTER,
+                target.as_raw(),
+                index as libc::c_ulong,
+                std::ptr::null_mut::<libc::sock_filter>(),
+            )
+        };
+        match Errno::result(len) {
+            Ok(_) => {}
+            Err(Errno::ENOENT) => break,
+            Err(why) => return Err(why),
+        }
+
+        let mut filter = vec![
+            libc::sock_filter {
+                code: 0,
+                jt: 0,
+                jf: 0,
+                k: 0
+            };
+            len as usize
+        ];
+        Errno::result(unsafe {
+            libc::ptrace(
+                PTRACE_SECCOMP_GET_FILTER,
+                target.as_raw(),
+                index as libc::c_ulong,
+                filter.as_mut_ptr(),
+            )
+        })?;
+        filters.push(filter);
+    }
+    filters.reverse();
+
+    Ok(filters)
+}
+
+fn set_seccomp_filter(filter: &[libc::sock_filter]) -> Result<(), Errno> {
+    let program = libc::sock_fprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_ptr() as *mut libc::sock_filter,
+    };
+    Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0,
+            &program as *const libc::sock_fprog,
+        )
+    })?;
+
+    Ok(())
+}
+
+fn prctl(option: libc::c_int, arg2: libc::c_ulong, arg3: libc::c_ulong) -> Result<(), Errno> {
+    Errno::result(unsafe { libc::prctl(option, arg2, arg3, 0, 0) })?;
+
+    Ok(())
+}
+
+/// 実効、許可、継承可能ケーパビリティを`caps`にする
+fn capset(caps: u64) -> Result<(), Errno> {
+    #[repr(C)]
+    struct Header {
+        version: u32,
+        pid: libc::c_int,
+    }
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Data {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    let mut header = Header {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [caps as u32, (caps >> 32) as u32].map(|caps| Data {
+        effective: caps,
+        permitted: caps,
+        inheritable: caps,
+    });
+    Errno::result(unsafe {
+        libc::syscall(libc::SYS_capset, &mut header as *mut Header, data.as_ptr())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status() {
+        let status = "Name:\tapp\nUid:\t1000\t1000\t1000\t1000\nCapInh:\t0000000000000000\n\
+CapPrm:\t0000000000000000\nCapEff:\t00000000a80425fb\nCapBnd:\t00000000a80425fb\n\
+CapAmb:\t0000000000000000\nNoNewPrivs:\t1\nSeccomp:\t2\nSeccomp_filters:\t1\n";
+        assert_eq!(
+            parse_status(status),
+            Some(Status {
+                cap_eff: 0xa80425fb,
+                cap_bnd: 0xa80425fb,
+                no_new_privs: true,
+                seccomp: 2,
+            })
+        );
+        assert_eq!(parse_status("Name:\tapp\n"), None);
+    }
+}
@@ -1,3 +1,7 @@
+use crate::cgroup;
+use crate::container::{FsView, Runtime, Selector};
+use crate::namespace;
+use std::path::{Path, PathBuf};
 use std::{error, fmt};
 
 #[derive(Debug)]
@@ -110,6 +114,17 @@ impl<RW: Reader + Writer> SettingHandler<RW> {
 #[derive(Debug, PartialEq)]
 pub struct Setting {
     docker_container_id: String,
+    runtime: Runtime,
+    fs_view: FsView,
+    selector: Option<Selector>,
+    target_name: Option<String>,
+    pid: Option<u32>,
+    start_time: Option<u64>,
+    rootfs: Option<PathBuf>,
+    namespaces: Vec<namespace::Kind>,
+    mount_namespace: bool,
+    cgroup: Option<cgroup::Placement>,
+    match_security: bool,
     shell: Shell,
     commands: Vec<String>,
 }
@@ -120,6 +135,17 @@ impl Setting {
 
         Setting {
             docker_container_id: docker_container_id.to_string(),
+            runtime: Runtime::Docker,
+            fs_view: FsView::MergedDir,
+            selector: None,
+            target_name: None,
+            pid: None,
+            start_time: None,
+            rootfs: None,
+            namespaces: namespace::DEFAULT_KINDS.to_vec(),
+            mount_namespace: false,
+            cgroup: None,
+            match_security: false,
             shell: shell,
             commands: commands,
         }
@@ -129,6 +155,119 @@ impl Setting {
         &self.docker_container_id
     }
 
+    /// デバック対象コンテナが再作成された場合に、新しいコンテナIDへ更新する
+    pub fn set_docker_container_id(&mut self, docker_container_id: &str) {
+        self.docker_container_id = docker_container_id.to_string();
+    }
+
+    /// デバック対象コンテナを管理しているコンテナランタイム
+    pub fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+
+    pub fn set_runtime(&mut self, runtime: Runtime) {
+        self.runtime = runtime;
+    }
+
+    /// デバック対象コンテナのファイルシステムの参照方法
+    ///
+    /// overlay2以外のストレージドライバでは`/proc/<PID>/root`を参照する
+    pub fn fs_view(&self) -> FsView {
+        self.fs_view
+    }
+
+    pub fn set_fs_view(&mut self, fs_view: FsView) {
+        self.fs_view = fs_view;
+    }
+
+    /// ラベルでデバック対象コンテナを指定した場合のセレクタ
+    ///
+    /// コンテナが再作成されIDが変わっても、execで再度選択するために用いる
+    pub fn selector(&self) -> Option<&Selector> {
+        self.selector.as_ref()
+    }
+
+    pub fn set_selector(&mut self, selector: Selector) {
+        self.selector = Some(selector);
+    }
+
+    /// デバック対象のDockerコンテナの名前
+    ///
+    /// コンテナが再作成されIDが変わっても、execで名前から再度選択するために用いる
+    pub fn target_name(&self) -> Option<&str> {
+        self.target_name.as_deref()
+    }
+
+    pub fn set_target_name(&mut self, target_name: &str) {
+        self.target_name = Some(target_name.to_string());
+    }
+
+    /// launch時、または再起動を検知した時のデバック対象プロセスのPID
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// launch時のデバック対象プロセスの起動時刻
+    ///
+    /// PIDが再利用されていないかを確認するために用いる
+    pub fn start_time(&self) -> Option<u64> {
+        self.start_time
+    }
+
+    pub fn set_process(&mut self, pid: u32, start_time: u64) {
+        self.pid = Some(pid);
+        self.start_time = Some(start_time);
+    }
+
+    /// デバックコンテナに挿入したrootfsのパス
+    ///
+    /// デバック対象コンテナが再起動した場合に、overlayfsを再マウントするために用いる
+    pub fn rootfs(&self) -> Option<&Path> {
+        self.rootfs.as_deref()
+    }
+
+    pub fn set_rootfs(&mut self, rootfs: &Path) {
+        self.rootfs = Some(rootfs.to_path_buf());
+    }
+
+    /// デバック対象コンテナと共有するnamespace
+    pub fn namespaces(&self) -> &[namespace::Kind] {
+        &self.namespaces
+    }
+
+    pub fn set_namespaces(&mut self, namespaces: Vec<namespace::Kind>) {
+        self.namespaces = namespaces;
+    }
+
+    /// デバック対象のmount namespaceに入り、デバックコンテナを空のディレクトリに持ち込むかどうか
+    ///
+    /// `false`の場合はデバックコンテナのmergedにchrootする
+    pub fn mount_namespace(&self) -> bool {
+        self.mount_namespace
+    }
+
+    pub fn set_mount_namespace(&mut self, mount_namespace: bool) {
+        self.mount_namespace = mount_namespace;
+    }
+
+    /// デバックセッションを置くcgroup。`None`の場合はinjeshを実行したcgroupのまま
+    pub fn cgroup(&self) -> Option<cgroup::Placement> {
+        self.cgroup
+    }
+
+    pub fn set_cgroup(&mut self, cgroup: cgroup::Placement) {
+        self.cgroup = Some(cgroup);
+    }
+
+    /// デバック対象と同じケーパビリティ、no_new_privs、seccompでコマンドを実行するかどうか
+    pub fn match_security(&self) -> bool {
+        self.match_security
+    }
+
+    pub fn set_match_security(&mut self, match_security: bool) {
+        self.match_security = match_security;
+    }
+
     pub fn commands(&self) -> &[String] {
         &self.commands
     }
@@ -1,3 +1,6 @@
+use crate::cgroup::Placement;
+use crate::container::{FsView, Runtime, Selector};
+use crate::namespace;
 use crate::setting::{Reader, Setting, Shell, Writer};
 use serde::{Deserialize, Serialize};
 use std::str;
@@ -12,6 +15,10 @@ pub enum Error {
     UnExpectedShell,
     UnExpectedCommand,
     UnexpectedContainerId,
+    UnexpectedRuntime,
+    UnexpectedFsView,
+    UnexpectedNamespace,
+    UnexpectedCgroup,
     Parse,
 }
 
@@ -21,6 +28,10 @@ impl fmt::Display for Error {
             Error::UnExpectedShell => write!(f, "setting_yaml: unexpected shell"),
             Error::UnExpectedCommand => write!(f, "setting_yaml: unexpected command"),
             Error::UnexpectedContainerId => write!(f, "setting_yaml: unexpected container id"),
+            Error::UnexpectedRuntime => write!(f, "setting_yaml: unexpected runtime"),
+            Error::UnexpectedFsView => write!(f, "setting_yaml: unexpected fs_view"),
+            Error::UnexpectedNamespace => write!(f, "setting_yaml: unexpected namespace"),
+            Error::UnexpectedCgroup => write!(f, "setting_yaml: unexpected cgroup"),
             Error::Parse => write!(f, "setting_yaml: parse error"),
         }
     }
@@ -33,6 +44,57 @@ struct YamlSetting {
     docker_container_id: String,
     shell: String,
     commands: Vec<String>,
+    // 追加前に作成された設定ファイルはDockerのみ
+    #[serde(default = "default_runtime")]
+    runtime: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    containerd_namespace: Option<String>,
+    #[serde(default = "default_fs_view")]
+    fs_view: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    selector: Option<YamlSelector>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    start_time: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rootfs: Option<PathBuf>,
+    #[serde(default = "default_namespaces")]
+    namespaces: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    mount_namespace: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cgroup: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    match_security: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct YamlSelector {
+    labels: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    replica: Option<usize>,
+}
+
+fn default_runtime() -> String {
+    Runtime::Docker.to_string()
+}
+
+fn default_fs_view() -> String {
+    FsView::MergedDir.to_string()
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn default_namespaces() -> Vec<String> {
+    namespace::DEFAULT_KINDS
+        .iter()
+        .map(|kind| kind.to_string())
+        .collect()
 }
 
 pub struct YamlReaderWriter {
@@ -59,11 +121,56 @@ impl Reader for YamlReaderWriter {
             _ => return Err(Error::UnExpectedShell)?,
         };
 
-        Ok(Setting::new(
+        let mut runtime: Runtime = setting_yaml
+            .runtime
+            .parse()
+            .map_err(|_| Error::UnexpectedRuntime)?;
+        if let (Runtime::Containerd(_), Some(namespace)) =
+            (&runtime, setting_yaml.containerd_namespace)
+        {
+            runtime = Runtime::Containerd(namespace);
+        }
+
+        let fs_view: FsView = setting_yaml
+            .fs_view
+            .parse()
+            .map_err(|_| Error::UnexpectedFsView)?;
+
+        let namespaces = setting_yaml
+            .namespaces
+            .iter()
+            .map(|kind| kind.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| Error::UnexpectedNamespace)?;
+
+        let mut setting = Setting::new(
             &setting_yaml.docker_container_id,
             shell,
             &setting_yaml.commands,
-        ))
+        );
+        setting.set_runtime(runtime);
+        setting.set_fs_view(fs_view);
+        if let Some(selector) = setting_yaml.selector {
+            setting.set_selector(Selector::new(selector.labels, selector.replica));
+        }
+        if let Some(target_name) = setting_yaml.target_name {
+            setting.set_target_name(&target_name);
+        }
+        if let (Some(pid), Some(start_time)) = (setting_yaml.pid, setting_yaml.start_time) {
+            setting.set_process(pid, start_time);
+        }
+        if let Some(rootfs) = setting_yaml.rootfs {
+            setting.set_rootfs(&rootfs);
+        }
+        setting.set_namespaces(namespaces);
+        setting.set_mount_namespace(setting_yaml.mount_namespace);
+        if let Some(cgroup) = setting_yaml.cgroup {
+            let cgroup: Placement = cgroup.parse().map_err(|_| Error::UnexpectedCgroup)?;
+            setting.set_cgroup(cgroup);
+        }
+        setting.set_match_security(setting_yaml.match_security);
+
+        Ok(setting)
     }
 }
 
@@ -78,6 +185,28 @@ impl Writer for YamlReaderWriter {
             docker_container_id: setting.docker_container_id().to_string(),
             shell: setting.shell().to_string(),
             commands: commands,
+            runtime: setting.runtime().to_string(),
+            containerd_namespace: setting
+                .runtime()
+                .containerd_namespace()
+                .map(|namespace| namespace.to_string()),
+            fs_view: setting.fs_view().to_string(),
+            selector: setting.selector().map(|selector| YamlSelector {
+                labels: selector.labels().to_vec(),
+                replica: selector.replica(),
+            }),
+            target_name: setting.target_name().map(|name| name.to_string()),
+            pid: setting.pid(),
+            start_time: setting.start_time(),
+            rootfs: setting.rootfs().map(|rootfs| rootfs.to_path_buf()),
+            namespaces: setting
+                .namespaces()
+                .iter()
+                .map(|kind| kind.to_string())
+                .collect(),
+            mount_namespace: setting.mount_namespace(),
+            cgroup: setting.cgroup().map(|cgroup| cgroup.to_string()),
+            match_security: setting.match_security(),
         };
 
         let yaml_string = serde_yaml::to_string(&yaml_setting)?;
@@ -85,6 +214,7 @@ impl Writer for YamlReaderWriter {
         let mut setting_file = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .read(false)
             .open(&self.setting_file_path)?;
 
@@ -0,0 +1,63 @@
+//! APIクライアントのテストで使う、Unixソケットのサーバ
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::thread;
+
+/// テストごとに異なるパスのUnixソケットを作成する
+pub fn bind(name: &str) -> (PathBuf, UnixListener) {
+    let socket = std::env::temp_dir().join(format!("injesh-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).unwrap();
+
+    (socket, listener)
+}
+
+/// HTTPのリクエストに、`responses`を順に返す
+///
+/// 接続が閉じられた場合は次の接続を待つ。受け取ったリクエスト行を、接続ごとにまとめて返す
+pub fn serve(
+    name: &str,
+    responses: Vec<String>,
+) -> (PathBuf, thread::JoinHandle<Vec<Vec<String>>>) {
+    let (socket, listener) = bind(name);
+
+    let handle = thread::spawn(move || {
+        let mut responses = responses.into_iter().peekable();
+        let mut connections = Vec::new();
+        while responses.peek().is_some() {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request_lines = Vec::new();
+            while responses.peek().is_some() {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap() == 0 {
+                    break;
+                }
+                request_lines.push(request_line.trim_end().to_string());
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                let response = responses.next().unwrap();
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            connections.push(request_lines);
+        }
+        connections
+    });
+
+    (socket, handle)
+}
+
+/// ステータスコードが`status`で、ボディがJSONの`body`のレスポンス
+pub fn json(status: u16, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
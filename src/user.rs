@@ -29,6 +29,12 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {}
 
+#[cfg(target_os = "linux")]
+/// ユーザのホームディレクトリ(sudoで実行された場合はsudoを実行したユーザのもの)
+pub fn home_dir() -> Result<String, Box<dyn std::error::Error>> {
+    linux::home_dir()
+}
+
 #[derive(Debug)]
 pub struct User {
     injesh_home: String,
@@ -78,6 +78,14 @@ impl Passwd {
 #[cfg(target_os = "linux")]
 /// ユーザのホームディレクトリを`/etc/passwd`から検索し、`~/.injesh`を返却する
 pub fn injesh_home_dir() -> Result<String, Box<dyn std::error::Error>> {
+    Ok(format!("{}/{}", home_dir()?, ".injesh"))
+}
+
+#[cfg(target_os = "linux")]
+/// ユーザのホームディレクトリを`/etc/passwd`から検索し、返却する
+///
+/// sudoで実行された場合は、sudoを実行したユーザのホームディレクトリ
+pub fn home_dir() -> Result<String, Box<dyn std::error::Error>> {
     let sudo_user = match env::var("SUDO_USER") {
         Ok(sudo_user) => sudo_user,
         Err(_) => match env::var("USER") {
@@ -89,7 +97,7 @@ pub fn injesh_home_dir() -> Result<String, Box<dyn std::error::Error>> {
     let passwd = Passwd::parse_passwd()?;
     for p in passwd {
         if p.user_name == sudo_user {
-            return Ok(format!("{}/{}", p.home_dir.clone(), ".injesh"));
+            return Ok(p.home_dir);
         }
     }
 
@@ -3,6 +3,7 @@
 //! - check initialized
 //! - getting PID from docker container name or id
 //! - generating rootfs from image server
+//! - generating debug container names
 
 pub fn check_initialized() -> Result<(), Box<dyn std::error::Error>> {
     let user_info = crate::user::User::new()?;
@@ -16,6 +17,22 @@ pub fn check_initialized() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// デバックコンテナの名前を、デバック対象コンテナの名前から`<CONTAINER_NAME>-debug`の形で生成する
+///
+/// 既に存在する場合は`<CONTAINER_NAME>-debug-2`のように番号を付ける
+pub fn generate_name(user: &crate::user::User, container_name: &str) -> String {
+    let base = format!("{}-debug", container_name);
+    let exists = |name: &str| std::path::Path::new(user.containers()).join(name).exists();
+
+    let mut name = base.clone();
+    let mut number = 2;
+    while exists(&name) {
+        name = format!("{}-{}", base, number);
+        number += 1;
+    }
+    name
+}
+
 // mod tests {
 //     use super::*;
 